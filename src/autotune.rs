@@ -0,0 +1,183 @@
+use crate::mvrprocessor::MotionVector;
+
+// Automatic SAD and magnitude thresholds.
+//
+// Fixed thresholds are right for one camera at one bitrate in one light. In
+// auto mode we learn the noise instead: over quiet frames (nothing tracked,
+// hardly anything active, no illumination change) the SAD and magnitude of all blocks are
+// collected in slowly fading histograms, and the thresholds are put at a
+// percentile of those, e.g. 99.5 lets through 0.5% of what noise looks like.
+// Until enough quiet frames were seen, the configured thresholds are used.
+//
+// Should the noise rise (it gets dark) the thresholds may be too low for any
+// frame to look quiet; after a long enough stretch of busy frames, we learn
+// from them anyway.
+
+pub struct AutoTuner {
+	sad: Vec<f32>,
+	mag: Vec<f32>,
+	quiet_frames: usize,
+	busy_frames: usize,
+	sad_threshold: u32,
+	mag_threshold: f32,
+}
+
+const SAD_BIN: usize = 8;
+const SAD_BINS: usize = 65536 / SAD_BIN;
+const MAG_BIN: f32 = 0.25;
+const MAG_BINS: usize = 800;		// max magnitude of i8 vectors is ~181
+
+const LEARN_FRAMES: usize = 30;
+const QUIET_COVERAGE: f32 = 0.05;	// at most this ratio of blocks active
+const MAX_BUSY_FRAMES: usize = 300;
+const DECAY: f32 = 0.98;			// per quiet frame, so ~35 frames half life
+
+impl AutoTuner {
+	pub fn new(sad_threshold: u32, mag_threshold: f32) -> AutoTuner {
+		AutoTuner {
+			sad: vec![0.0; SAD_BINS],
+			mag: vec![0.0; MAG_BINS],
+			quiet_frames: 0,
+			busy_frames: 0,
+			sad_threshold,
+			mag_threshold,
+		}
+	}
+
+	// New configured thresholds; only in effect until enough has been learned.
+	pub fn set_fallback(&mut self, sad_threshold: u32, mag_threshold: f32) {
+		if self.quiet_frames < LEARN_FRAMES {
+			self.sad_threshold = sad_threshold;
+			self.mag_threshold = mag_threshold;
+		}
+	}
+
+	// (SAD, magnitude) thresholds in effect.
+	pub fn thresholds(&self) -> (u32, f32) {
+		(self.sad_threshold, self.mag_threshold)
+	}
+
+	// 'quiet': nothing is being tracked and the frame is no illumination change.
+	pub fn observe(&mut self, vectors: &[MotionVector], candidates: usize, quiet: bool, percentile: f32)
+	{
+		if !quiet || candidates as f32 > vectors.len() as f32 * QUIET_COVERAGE {
+			self.busy_frames += 1;
+			if self.busy_frames < MAX_BUSY_FRAMES {
+				return;
+			}
+		} else {
+			self.busy_frames = 0;
+		}
+
+		self.sad.iter_mut().for_each(|v| *v *= DECAY);
+		self.mag.iter_mut().for_each(|v| *v *= DECAY);
+
+		for v in vectors {
+			self.sad[v.sad as usize / SAD_BIN] += 1.0;
+			self.mag[((v.mag / MAG_BIN) as usize).min(MAG_BINS - 1)] += 1.0;
+		}
+
+		self.quiet_frames += 1;
+		if self.quiet_frames < LEARN_FRAMES {
+			return;
+		}
+
+		let fraction = percentile.clamp(0.0, 100.0) / 100.0;
+
+		// Thresholds are exclusive for SAD and inclusive for magnitude; take
+		// the upper edge of the bin in both cases.
+		self.sad_threshold = ((percentile_bin(&self.sad, fraction) + 1) * SAD_BIN - 1) as u32;
+		self.mag_threshold = (percentile_bin(&self.mag, fraction) + 1) as f32 * MAG_BIN;
+	}
+}
+
+fn percentile_bin(histogram: &[f32], fraction: f32) -> usize
+{
+	let total: f32 = histogram.iter().sum();
+	let mut seen = 0.0;
+
+	for (bin, count) in histogram.iter().enumerate() {
+		seen += count;
+		if seen >= total * fraction {
+			return bin;
+		}
+	}
+
+	histogram.len() - 1
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// 100 blocks of noise: SAD 0-99 x 10, magnitude 0.0-9.9.
+	fn noise() -> Vec<MotionVector> {
+		(0..100)
+			.map(|i| {
+				let mut v = MotionVector::new();
+				v.sad = i * 10;
+				v.mag = i as f32 / 10.0;
+				v
+			})
+			.collect()
+	}
+
+	#[test]
+	fn percentile_of_histogram() {
+		let histogram = [1.0, 2.0, 3.0, 4.0];
+
+		assert_eq!(percentile_bin(&histogram, 0.0), 0);
+		assert_eq!(percentile_bin(&histogram, 0.1), 0);
+		assert_eq!(percentile_bin(&histogram, 0.3), 1);
+		assert_eq!(percentile_bin(&histogram, 0.6), 2);
+		assert_eq!(percentile_bin(&histogram, 1.0), 3);
+	}
+
+	#[test]
+	fn configured_thresholds_until_learned() {
+		let mut tuner = AutoTuner::new(250, 2.0);
+		let noise = noise();
+
+		for _ in 0..LEARN_FRAMES - 1 {
+			tuner.observe(&noise, 0, true, 90.0);
+		}
+		assert_eq!(tuner.thresholds(), (250, 2.0));
+
+		tuner.set_fallback(300, 3.0);
+		assert_eq!(tuner.thresholds(), (300, 3.0));
+
+		tuner.observe(&noise, 0, true, 90.0);
+		assert_ne!(tuner.thresholds(), (300, 3.0));
+
+		// Learned; fallbacks no longer matter.
+		let learned = tuner.thresholds();
+		tuner.set_fallback(250, 2.0);
+		assert_eq!(tuner.thresholds(), learned);
+	}
+
+	#[test]
+	fn percentile_sets_both_thresholds() {
+		let mut tuner = AutoTuner::new(250, 2.0);
+		let noise = noise();
+
+		for _ in 0..LEARN_FRAMES {
+			tuner.observe(&noise, 0, true, 89.5);
+		}
+
+		// 89.5% of the noise is reached at SAD 890 (bin 888-895) and
+		// magnitude 8.9 (bin 8.75-9.0).
+		assert_eq!(tuner.thresholds(), (895, 9.0));
+	}
+
+	#[test]
+	fn busy_frames_are_not_learned_from() {
+		let mut tuner = AutoTuner::new(250, 2.0);
+		let noise = noise();
+
+		for _ in 0..LEARN_FRAMES {
+			tuner.observe(&noise, 0, false, 90.0);
+			tuner.observe(&noise, 50, true, 90.0);
+		}
+		assert_eq!(tuner.thresholds(), (250, 2.0));
+	}
+}
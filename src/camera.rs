@@ -0,0 +1,86 @@
+use std::io::{BufRead, Read};
+use serde::Serialize;
+
+// Which camera an input belongs to.
+//
+// Every camera is known by an ID, which ends up in every message so outputs of
+// several cameras can be told apart. By default that is the port the camera
+// connected to; ports can be given names with --cameras, and with --handshake
+// an input sends its own ID as a first line (e.g. "garden\n") before the
+// first frame.
+
+const MAX_ID_LENGTH: u64 = 256;
+
+#[derive(Serialize)]
+pub struct Tagged<'a, T: Serialize> {
+	pub camera: &'a str,
+	#[serde(flatten)]
+	pub message: &'a T,
+}
+
+// Parses "8002=garden,8003" into [("8002", Some("garden")), ("8003", None)].
+pub fn parse_ports(s: &str) -> Result<Vec<(String, Option<String>)>, String>
+{
+	s.split(',')
+		.map(|p| p.trim())
+		.filter(|p| !p.is_empty())
+		.map(|p| {
+			let (port, id) = match p.split_once('=') {
+				Some((port, id)) => (port.trim(), Some(id.trim().to_string())),
+				None => (p, None),
+			};
+
+			port.parse::<u16>().map_err(|e| format!("'{}': {}", p, e))?;
+
+			Ok((port.to_string(), id.filter(|id| !id.is_empty())))
+		})
+		.collect()
+}
+
+// 'camera' made safe for use in file names.
+pub fn file_name(camera: &str) -> String
+{
+	camera.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' }).collect()
+}
+
+// Reads the ID line an input sends first. Limited in length, in case the
+// input is not sending one and we're reading frame data instead.
+pub fn handshake<R: BufRead>(stream: &mut R) -> Result<String, String>
+{
+	let mut line = String::new();
+
+	stream.by_ref().take(MAX_ID_LENGTH).read_line(&mut line).map_err(|e| e.to_string())?;
+
+	let id = line.trim();
+	if id.is_empty() || !line.ends_with('\n') {
+		return Err("no camera ID received".to_string());
+	}
+
+	Ok(id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn ports_are_parsed() {
+		assert_eq!(parse_ports("8002=garden, 8003,"), Ok(vec![
+			("8002".to_string(), Some("garden".to_string())),
+			("8003".to_string(), None),
+		]));
+		assert_eq!(parse_ports("8004="), Ok(vec![("8004".to_string(), None)]));
+		assert!(parse_ports("garden").is_err());
+		assert!(parse_ports("70000=garden").is_err());
+	}
+
+	#[test]
+	fn handshake_reads_one_line() {
+		let mut input = &b"garden\nframes"[..];
+		assert_eq!(handshake(&mut input), Ok("garden".to_string()));
+		assert_eq!(input, b"frames");
+
+		assert!(handshake(&mut &b"\n"[..]).is_err());
+		assert!(handshake(&mut &[b'x'; 300][..]).is_err());
+	}
+}
@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use crate::mvrprocessor::Cluster;
+
+// Coarse, heuristic classification of tracks in 'history'.
+//
+// No ML here; just what we can tell from how a track looks and moves over its
+// lifetime:
+// - size (blocks, or metres if there is a perspective calibration)
+// - aspect ratio of its bounding box
+// - speed (net displacement of its centre per second)
+// - direction coherence (1.0: all its vectors agree over time, 0.0: random)
+// - persistence (age)
+// - straightness of its path (net displacement / distance travelled)
+//
+// Lighting changes are huge and go nowhere, vegetation sways in place for a
+// long time, vehicles are wide and fast, people are tall and walk fairly
+// straight, animals are small and low. Anything else is unknown.
+//
+// Thresholds can be tweaked from a JSON file (any that are left out keep their
+// default), e.g.: { "vehicleMinSpeed": 15, "personMaxAspect": 0.7 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Class {
+	#[default]
+	Unknown,
+	Person,
+	Vehicle,
+	Animal,
+	Vegetation,
+	Lighting,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Thresholds {
+	pub min_age: u64,					// ms; younger tracks are unknown
+	pub lighting_min_coverage: f32,		// fraction of the frame
+	pub vegetation_min_age: u64,		// ms
+	pub vegetation_max_displacement: f32,	// blocks
+	pub vegetation_max_coherence: f32,
+	pub vehicle_min_speed: f32,			// blocks per second
+	pub vehicle_min_aspect: f32,
+	pub vehicle_min_width: f32,			// metres, if calibrated
+	pub person_max_aspect: f32,
+	pub person_min_straightness: f32,
+	pub person_max_speed: f32,			// blocks per second
+	pub person_min_height: f32,			// metres, if calibrated
+	pub person_max_height: f32,			// metres, if calibrated
+	pub animal_max_area: f32,			// blocks
+	pub animal_max_height: f32,			// metres, if calibrated
+}
+
+impl Default for Thresholds {
+	fn default() -> Thresholds {
+		Thresholds {
+			min_age: 500,
+			lighting_min_coverage: 0.3,
+			vegetation_min_age: 3000,
+			vegetation_max_displacement: 3.0,
+			vegetation_max_coherence: 0.5,
+			vehicle_min_speed: 10.0,
+			vehicle_min_aspect: 1.2,
+			vehicle_min_width: 2.0,
+			person_max_aspect: 0.8,
+			person_min_straightness: 0.5,
+			person_max_speed: 10.0,
+			person_min_height: 1.0,
+			person_max_height: 2.3,
+			animal_max_area: 30.0,
+			animal_max_height: 1.0,
+		}
+	}
+}
+
+// What we remember about a track between frames.
+struct TrackStats {
+	origin: (f32, f32),
+	last: (f32, f32),
+	distance: f32,
+	dir_x: f32,
+	dir_y: f32,
+	samples: usize,
+}
+
+pub struct Classifier {
+	thresholds: Thresholds,
+	frame_blocks: f32,
+	tracks: HashMap<usize, TrackStats>,
+}
+
+impl Classifier {
+	pub fn new(thresholds: Thresholds, frame_blocks: usize) -> Classifier {
+		Classifier {
+			thresholds,
+			frame_blocks: frame_blocks as f32,
+			tracks: HashMap::new(),
+		}
+	}
+
+	// New thresholds apply from the next frame on; what we know of tracks is kept.
+	pub fn set_thresholds(&mut self, thresholds: Thresholds) {
+		self.thresholds = thresholds;
+	}
+
+	// Updates stats of tracks seen this frame and sets their class.
+	pub fn classify(&mut self, history: &mut [Cluster], now: u128)
+	{
+		self.tracks.retain(|id, _| history.iter().any(|c| c.id == *id));
+
+		for track in history.iter_mut().filter(|c| c.active == now) {
+			let centre = track.centre();
+			let stats = self.tracks.entry(track.id).or_insert(TrackStats {
+				origin: centre,
+				last: centre,
+				distance: 0.0,
+				dir_x: 0.0,
+				dir_y: 0.0,
+				samples: 0,
+			});
+
+			stats.distance += distance(stats.last, centre);
+			stats.last = centre;
+			stats.dir_x += track.dir.to_radians().cos();
+			stats.dir_y += track.dir.to_radians().sin();
+			stats.samples += 1;
+
+			track.class = Some(classify(&self.thresholds, track, stats, self.frame_blocks));
+		}
+	}
+}
+
+fn classify(t: &Thresholds, track: &Cluster, stats: &TrackStats, frame_blocks: f32) -> Class
+{
+	let width = (track.bbox[1] - track.bbox[3] + 1) as f32;
+	let height = (track.bbox[2] - track.bbox[0] + 1) as f32;
+	let area = width * height;
+	let aspect = width / height;
+
+	let displacement = distance(stats.origin, stats.last);
+	let straightness = if stats.distance > 0.0 { displacement / stats.distance } else { 0.0 };
+	let coherence = (stats.dir_x * stats.dir_x + stats.dir_y * stats.dir_y).sqrt() / stats.samples as f32;
+	let speed = if track.age > 0 { displacement / (track.age as f32 / 1000.0) } else { 0.0 };
+
+	if area / frame_blocks >= t.lighting_min_coverage {
+		return Class::Lighting;
+	}
+
+	if track.age < t.min_age as u128 {
+		return Class::Unknown;
+	}
+
+	if track.age >= t.vegetation_min_age as u128
+		&& displacement <= t.vegetation_max_displacement
+		&& coherence <= t.vegetation_max_coherence {
+		return Class::Vegetation;
+	}
+
+	if speed >= t.vehicle_min_speed
+		&& aspect >= t.vehicle_min_aspect
+		&& track.meters.is_none_or(|m| m[0] >= t.vehicle_min_width) {
+		return Class::Vehicle;
+	}
+
+	if aspect <= t.person_max_aspect
+		&& straightness >= t.person_min_straightness
+		&& speed <= t.person_max_speed
+		&& track.meters.is_none_or(|m| m[1] >= t.person_min_height && m[1] <= t.person_max_height) {
+		return Class::Person;
+	}
+
+	if aspect > 1.0
+		&& area <= t.animal_max_area
+		&& track.meters.is_none_or(|m| m[1] <= t.animal_max_height) {
+		return Class::Animal;
+	}
+
+	Class::Unknown
+}
+
+fn distance(a: (f32, f32), b: (f32, f32)) -> f32
+{
+	((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt()
+}
+
+pub fn load_thresholds(path: &str) -> Result<Thresholds, String>
+{
+	let json = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+	serde_json::from_str(&json).map_err(|e| format!("{}: {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const FRAME: f32 = 10000.0;
+
+	// A 'width' by 'height' track, 'age' ms old.
+	fn track(width: i16, height: i16, age: u128) -> Cluster {
+		let mut c = Cluster::new();
+		c.bbox = [0, width - 1, height - 1, 0];
+		c.age = age;
+		c
+	}
+
+	// Moved 'displacement' blocks over a path of 'distance', with its vectors
+	// agreeing by 'coherence'.
+	fn moved(displacement: f32, distance: f32, coherence: f32) -> TrackStats {
+		TrackStats {
+			origin: (0.0, 0.0),
+			last: (displacement, 0.0),
+			distance,
+			dir_x: coherence,
+			dir_y: 0.0,
+			samples: 1,
+		}
+	}
+
+	fn class(track: &Cluster, stats: &TrackStats) -> Class {
+		classify(&Thresholds::default(), track, stats, FRAME)
+	}
+
+	#[test]
+	fn lighting_covers_enough_of_the_frame() {
+		let t = Thresholds::default();
+		let still = moved(0.0, 0.0, 0.0);
+
+		assert_eq!(classify(&t, &track(6, 5, 0), &still, 100.0), Class::Lighting);
+		assert_eq!(classify(&t, &track(6, 5, 0), &still, 101.0), Class::Unknown);
+	}
+
+	#[test]
+	fn young_tracks_are_unknown() {
+		assert_eq!(class(&track(4, 5, 499), &moved(2.5, 5.0, 1.0)), Class::Unknown);
+		assert_eq!(class(&track(4, 5, 500), &moved(2.5, 5.0, 1.0)), Class::Person);
+	}
+
+	#[test]
+	fn vegetation_sways_in_place() {
+		assert_eq!(class(&track(2, 2, 3000), &moved(3.0, 30.0, 0.5)), Class::Vegetation);
+		assert_eq!(class(&track(2, 2, 2999), &moved(3.0, 30.0, 0.5)), Class::Unknown);
+		assert_eq!(class(&track(2, 2, 3000), &moved(3.5, 30.0, 0.5)), Class::Unknown);
+		assert_eq!(class(&track(2, 2, 3000), &moved(3.0, 30.0, 0.6)), Class::Unknown);
+	}
+
+	#[test]
+	fn vehicles_are_fast_and_wide() {
+		assert_eq!(class(&track(6, 5, 1000), &moved(10.0, 10.0, 1.0)), Class::Vehicle);
+		// Too slow; small enough for an animal.
+		assert_eq!(class(&track(6, 5, 1000), &moved(9.5, 9.5, 1.0)), Class::Animal);
+		// Not wide enough.
+		assert_eq!(class(&track(5, 5, 1000), &moved(10.0, 10.0, 1.0)), Class::Unknown);
+
+		let mut narrow = track(6, 5, 1000);
+		narrow.meters = Some([1.9, 1.5]);
+		assert_ne!(class(&narrow, &moved(10.0, 10.0, 1.0)), Class::Vehicle);
+		narrow.meters = Some([2.0, 1.5]);
+		assert_eq!(class(&narrow, &moved(10.0, 10.0, 1.0)), Class::Vehicle);
+	}
+
+	#[test]
+	fn people_are_tall_and_walk_straight() {
+		assert_eq!(class(&track(4, 5, 1000), &moved(5.0, 10.0, 1.0)), Class::Person);
+		// Too wide.
+		assert_eq!(class(&track(5, 5, 1000), &moved(5.0, 10.0, 1.0)), Class::Unknown);
+		// Wandering about.
+		assert_eq!(class(&track(4, 5, 1000), &moved(5.0, 10.5, 1.0)), Class::Unknown);
+		// Too fast.
+		assert_eq!(class(&track(4, 5, 1000), &moved(10.0, 10.0, 1.0)), Class::Person);
+		assert_eq!(class(&track(4, 5, 1000), &moved(10.5, 10.5, 1.0)), Class::Unknown);
+	}
+
+	#[test]
+	fn people_are_of_human_height() {
+		let stats = moved(5.0, 10.0, 1.0);
+		let mut person = track(4, 5, 1000);
+
+		for (height, expected) in [(0.9, Class::Unknown), (1.0, Class::Person), (2.3, Class::Person), (2.4, Class::Unknown)] {
+			person.meters = Some([0.5, height]);
+			assert_eq!(class(&person, &stats), expected, "{} m", height);
+		}
+	}
+
+	#[test]
+	fn animals_are_small_and_low() {
+		let stats = moved(1.0, 1.0, 1.0);
+
+		assert_eq!(class(&track(6, 5, 1000), &stats), Class::Animal);
+		// Too big.
+		assert_eq!(class(&track(7, 5, 1000), &stats), Class::Unknown);
+		// Not lying down.
+		assert_eq!(class(&track(5, 5, 1000), &stats), Class::Unknown);
+
+		let mut animal = track(6, 5, 1000);
+		animal.meters = Some([1.0, 1.0]);
+		assert_eq!(class(&animal, &stats), Class::Animal);
+		animal.meters = Some([1.0, 1.1]);
+		assert_eq!(class(&animal, &stats), Class::Unknown);
+	}
+}
@@ -0,0 +1,139 @@
+use std::time::{Instant,SystemTime,UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+
+// The time (in ms) that all temporal logic (birth, age, expiration of
+// clusters) is computed on.
+//
+// Wall-clock time at processing time breaks as soon as frames are replayed from
+// a file, NTP adjusts the clock or frames arrive in a burst after buffering.
+// Instead, time is one of:
+// - Monotonic: wall-clock at start of connection + elapsed Instant
+// - FrameRate: wall-clock at start of connection + frame number / frame rate
+// - Stream:    timestamps that come with the frames themselves
+//
+// Whatever the source, the clock never goes backwards.
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ClockSource {
+	Monotonic,
+	FrameRate,
+	Stream,
+}
+
+impl std::str::FromStr for ClockSource {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_uppercase().as_str() {
+			"MONOTONIC" => Ok(ClockSource::Monotonic),
+			"FRAMERATE" => Ok(ClockSource::FrameRate),
+			"STREAM" => Ok(ClockSource::Stream),
+			_ => Err(format!("unknown clock '{}'", s)),
+		}
+	}
+}
+
+pub struct FrameClock {
+	source: ClockSource,
+	fps: f32,
+	start: Instant,
+	start_epoch: u128,
+	last: u128,
+}
+
+impl FrameClock {
+	pub fn new(source: ClockSource, fps: f32) -> FrameClock {
+		FrameClock {
+			source,
+			fps,
+			start: Instant::now(),
+			start_epoch: epoch_ms(),
+			last: 0,
+		}
+	}
+
+	// Whether frames need to come with a timestamp.
+	pub fn wants_timestamps(&self) -> bool {
+		self.source == ClockSource::Stream
+	}
+
+	// Time of the frame with the given number (counted from 0 at start of
+	// connection). 'stream_ts' is the timestamp that came with the frame, if any.
+	pub fn tick(&mut self, frame_number: u64, stream_ts: Option<u128>) -> u128
+	{
+		let t = match self.source {
+			ClockSource::Monotonic => self.start_epoch + self.start.elapsed().as_millis(),
+			ClockSource::FrameRate => self.start_epoch + (frame_number as f64 * 1000.0 / self.fps as f64) as u128,
+			ClockSource::Stream => stream_ts.unwrap_or(self.last),
+		};
+
+		self.last = self.last.max(t);
+		self.last
+	}
+}
+
+// --framerate; frame times are divided by it.
+pub fn parse_framerate(s: &str) -> Result<f32, String>
+{
+	match s.parse::<f32>() {
+		Ok(fps) if fps > 0.0 && fps.is_finite() => Ok(fps),
+		Ok(_) => Err("must be more than 0".to_string()),
+		Err(e) => Err(e.to_string()),
+	}
+}
+
+pub fn epoch_ms() -> u128 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.expect("Time was weird")
+		.as_millis()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn frame_rate_follows_frame_numbers() {
+		let mut clock = FrameClock::new(ClockSource::FrameRate, 4.0);
+		let start = clock.tick(0, None);
+
+		assert_eq!(clock.tick(1, None), start + 250);
+		assert_eq!(clock.tick(10, None), start + 2500);
+	}
+
+	#[test]
+	fn never_goes_backwards() {
+		let mut clock = FrameClock::new(ClockSource::Stream, 30.0);
+
+		assert_eq!(clock.tick(0, Some(1000)), 1000);
+		assert_eq!(clock.tick(1, Some(900)), 1000);
+		assert_eq!(clock.tick(2, None), 1000);
+		assert_eq!(clock.tick(3, Some(1100)), 1100);
+
+		let mut clock = FrameClock::new(ClockSource::FrameRate, 4.0);
+		let later = clock.tick(10, None);
+		assert_eq!(clock.tick(5, None), later);
+	}
+
+	#[test]
+	fn monotonic_does_not_decrease() {
+		let mut clock = FrameClock::new(ClockSource::Monotonic, 30.0);
+		let mut last = 0;
+
+		for n in 0..100 {
+			let t = clock.tick(n, None);
+			assert!(t >= last);
+			last = t;
+		}
+	}
+
+	#[test]
+	fn frame_rate_must_be_positive() {
+		assert_eq!(parse_framerate("25"), Ok(25.0));
+		assert!(parse_framerate("0").is_err());
+		assert!(parse_framerate("-5").is_err());
+		assert!(parse_framerate("fast").is_err());
+	}
+}
@@ -0,0 +1,325 @@
+use std::collections::BTreeMap;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use crate::{classify, daynight, direction, flow, perspective, tripwire, zones};
+use crate::AppArgs;
+
+// Configuration file.
+//
+// A JSON file with settings for all cameras ("defaults") and per camera
+// overrides ("cameras", by camera ID). Settings are named like the command
+// line options, without the dashes, e.g.:
+//
+//	{
+//		"defaults": { "sadthreshold": 300, "zones": "zones.json" },
+//		"cameras": {
+//			"garden": { "port": 8002, "minmagnitude": 3, "directions": "315-45" }
+//		}
+//	}
+//
+// Settings in the file take precedence over the command line. Zones, lines
+// and classes can be given inline or as the path of a file. A camera's "port"
+// makes it listen on that port as that camera.
+//
+// "profiles" are named sets of settings, applied on top of those of a camera
+// when it switches to them (see daynight.rs), e.g.:
+//
+//	"profiles": { "night": { "sadthreshold": 450, "minpoints": 6 } }
+
+#[derive(Debug, Deserialize, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+	#[serde(default)]
+	pub defaults: Map<String, Value>,
+	#[serde(default)]
+	pub cameras: BTreeMap<String, Map<String, Value>>,
+	#[serde(default)]
+	pub profiles: BTreeMap<String, Map<String, Value>>,
+}
+
+// Only make sense for the process as a whole.
+const GLOBAL_KEYS: [&str; 6] = ["listen", "metricsport", "metricslisten", "controlport", "controllisten", "handshake"];
+
+// Can not change while a camera is connected.
+const FIXED_KEYS: [&str; 4] = ["width", "height", "clock", "framerate"];
+
+// Files read or written; only to be set in the file or on the command line,
+// not by whoever can reach the control socket.
+const PATH_KEYS: [&str; 6] = ["config", "input", "heatmapfile", "snapshotdir", "debugdir", "recorddir"];
+
+// Read from a file when given as a string, rather than inline.
+const FILE_KEYS: [&str; 3] = ["zones", "lines", "classes"];
+
+// Loads the file at 'path' and applies its defaults to 'args'.
+pub fn load(path: &str, args: &mut AppArgs) -> Result<ConfigFile, String>
+{
+	let json = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+	let file: ConfigFile = serde_json::from_str(&json).map_err(|e| format!("{}: {}", path, e))?;
+
+	file.apply_defaults(args).map_err(|e| format!("{}: {}", path, e))?;
+
+	Ok(file)
+}
+
+impl ConfigFile {
+	// Applies the defaults to 'args' and adds listeners for cameras with a
+	// port. Every camera's settings are checked too, so that mistakes show up
+	// at start rather than when the camera connects.
+	fn apply_defaults(&self, args: &mut AppArgs) -> Result<(), String>
+	{
+		apply(args, &self.defaults, "defaults", false)?;
+
+		for (name, settings) in self.profiles.iter() {
+			apply(&mut args.clone(), settings, &format!("profiles.{}", name), true)?;
+		}
+		self.check_profiles(args, "defaults")?;
+
+		for (id, settings) in self.cameras.iter() {
+			let at = format!("cameras.{}", id);
+			self.for_camera(args, id, None)?;
+
+			if let Some(port) = settings.get("port") {
+				let port: u16 = value(port, &format!("{}.port", at))?;
+				args.cameras.retain(|(p, _)| *p != port.to_string());
+				args.cameras.push((port.to_string(), Some(id.clone())));
+			}
+		}
+
+		Ok(())
+	}
+
+	// Settings of camera 'id', based on 'args' (which have the defaults
+	// applied already), with 'profile' on top if given.
+	pub fn for_camera(&self, args: &AppArgs, id: &str, profile: Option<&str>) -> Result<AppArgs, String>
+	{
+		let mut args = args.clone();
+		let at = format!("cameras.{}", id);
+
+		if let Some(settings) = self.cameras.get(id) {
+			apply(&mut args, settings, &at, true)?;
+		}
+		self.check_profiles(&args, &at)?;
+
+		if let Some(name) = profile {
+			let settings = self.profiles.get(name).ok_or(format!("no profile '{}'", name))?;
+			apply(&mut args, settings, &format!("profiles.{}", name), true)?;
+		}
+
+		Ok(args)
+	}
+
+	fn check_profiles(&self, args: &AppArgs, at: &str) -> Result<(), String>
+	{
+		for (key, profile) in [("dayprofile", &args.dayprofile), ("nightprofile", &args.nightprofile)] {
+			if let Some(name) = profile {
+				if !self.profiles.contains_key(name) {
+					return Err(format!("{}.{}: no profile '{}'", at, key, name));
+				}
+			}
+		}
+
+		Ok(())
+	}
+}
+
+// Current value of setting 'key', or of all settings.
+pub fn get(args: &AppArgs, key: Option<&str>) -> Result<Value, String>
+{
+	let settings = serde_json::to_value(args).map_err(|e| e.to_string())?;
+
+	match key {
+		None => Ok(settings),
+		Some(key) => settings.get(key).cloned().ok_or(format!("{}: unknown setting", key)),
+	}
+}
+
+// 'args' with setting 'key' changed to 'value', for a connected camera.
+pub fn set(args: &AppArgs, key: &str, value: &Value) -> Result<AppArgs, String>
+{
+	if FIXED_KEYS.contains(&key) || key == "port" {
+		return Err(format!("{}: can not be changed while connected", key));
+	}
+
+	if PATH_KEYS.contains(&key) || (FILE_KEYS.contains(&key) && value.is_string()) {
+		return Err(format!("{}: paths can not be set over the control socket", key));
+	}
+
+	let mut args = args.clone();
+	let mut settings = Map::new();
+	settings.insert(key.to_string(), value.clone());
+
+	apply(&mut args, &settings, "", true)
+		.map_err(|e| e.trim_start_matches('.').to_string())?;
+
+	Ok(args)
+}
+
+fn apply(args: &mut AppArgs, settings: &Map<String, Value>, at: &str, camera: bool) -> Result<(), String>
+{
+	for (key, v) in settings.iter() {
+		let k = &format!("{}.{}", at, key);
+
+		if camera && GLOBAL_KEYS.contains(&key.as_str()) {
+			return Err(format!("{}: can only be set in defaults", k));
+		}
+
+		match key.as_str() {
+			"width" => args.width = value(v, k)?,
+			"height" => args.height = value(v, k)?,
+			"minmagnitude" => args.minmagnitude = value(v, k)?,
+			"epsilon" => args.epsilon = value(v, k)?,
+			"minpoints" => args.minpoints = value(v, k)?,
+			"listen" => args.listen = value(v, k)?,
+			"port" => if !camera { args.port = value::<u16>(v, k)?.to_string() },
+			"output" => args.output = value(v, k)?,
+			"ignore" => args.ignore = value(v, k)?,
+			"discardafter" => args.discardafter = value(v, k)?,
+			"sadthreshold" => args.sadthreshold = value(v, k)?,
+			"autotune" => args.autotune = value(v, k)?,
+			"dayprofile" => args.dayprofile = value(v, k)?,
+			"nightprofile" => args.nightprofile = value(v, k)?,
+			"nightschedule" => args.nightschedule = parsed(v, k, |s| daynight::parse_schedule(s).map(Some))?,
+			"nightnoise" => args.nightnoise = parsed(v, k, |s| daynight::parse_levels(s).map(Some))?,
+			"utcoffset" => args.utcoffset = value(v, k)?,
+			"nullframecoverage" => args.nullframecoverage = value(v, k)?,
+			"nullframefactor" => args.nullframefactor = value(v, k)?,
+			"suppressnullframes" => args.suppressnullframes = value(v, k)?,
+			"persistframes" => args.persistframes = value(v, k)?,
+			"persistwindow" => args.persistwindow = value(v, k)?,
+			"medianfilter" => args.medianfilter = value(v, k)?,
+			"maskopen" => args.maskopen = value(v, k)?,
+			"maskclose" => args.maskclose = value(v, k)?,
+			"warmupframes" => args.warmupframes = value(v, k)?,
+			"warmupms" => args.warmupms = value(v, k)?,
+			"clock" => args.clock = parsed(v, k, |s| s.parse())?,
+			"framerate" => args.framerate = value(v, k)?,
+			"zones" => args.zones = inline_or_file(v, k, zones::load_zones)?,
+			"lines" => args.lines = inline_or_file(v, k, tripwire::load_lines)?,
+			"directions" => args.directions = parsed(v, k, direction::parse_ranges)?,
+			"minclustermagnitude" => args.minclustermagnitude = value(v, k)?,
+			"minarea" => args.shape.min_area = value(v, k)?,
+			"maxarea" => args.shape.max_area = value(v, k)?,
+			"minwidth" => args.shape.min_width = value(v, k)?,
+			"maxwidth" => args.shape.max_width = value(v, k)?,
+			"minheight" => args.shape.min_height = value(v, k)?,
+			"maxheight" => args.shape.max_height = value(v, k)?,
+			"minaspect" => args.shape.min_aspect = value(v, k)?,
+			"maxaspect" => args.shape.max_aspect = value(v, k)?,
+			"pixelunits" => args.pixelunits = value(v, k)?,
+			"calibration" => args.calibration = parsed(v, k, perspective::parse_references)?,
+			"classes" => args.classes = inline_or_file(v, k, classify::load_thresholds)?,
+			"heatmapinterval" => args.heatmapinterval = value(v, k)?,
+			"heatmaphalflife" => args.heatmaphalflife = value(v, k)?,
+			"heatmapfile" => args.heatmapfile = value(v, k)?,
+			"flowinterval" => args.flowinterval = value(v, k)?,
+			"flowgrid" => args.flowgrid = parsed(v, k, flow::parse_grid)?,
+			"metricsport" => args.metricsport = value::<Option<u16>>(v, k)?.map(|p| p.to_string()),
+			"metricslisten" => args.metricslisten = value(v, k)?,
+			"controlport" => args.controlport = value::<Option<u16>>(v, k)?.map(|p| p.to_string()),
+			"controllisten" => args.controllisten = value(v, k)?,
+			"timing" => args.timing = value(v, k)?,
+			"snapshotonalarm" => args.snapshotonalarm = value(v, k)?,
+			"snapshotdir" => args.snapshotdir = value(v, k)?,
+			"debugdir" => args.debugdir = value(v, k)?,
+			"recorddir" => args.recorddir = value(v, k)?,
+			"prerollms" => args.prerollms = value(v, k)?,
+			"postrollms" => args.postrollms = value(v, k)?,
+			"degrade" => args.degrade = value(v, k)?,
+			"handshake" => args.handshake = value(v, k)?,
+			_ => return Err(format!("{}: unknown setting", k)),
+		}
+	}
+
+	validate(args, settings, at)
+}
+
+// Checks the values of the settings that were set.
+fn validate(args: &AppArgs, settings: &Map<String, Value>, at: &str) -> Result<(), String>
+{
+	let checks = [
+		(args.width > 0, "width", "must be more than 0"),
+		(args.height > 0, "height", "must be more than 0"),
+		(args.minpoints > 0, "minpoints", "must be more than 0"),
+		(args.epsilon > 0.0, "epsilon", "must be more than 0"),
+		(args.framerate > 0.0, "framerate", "must be more than 0"),
+		(args.autotune.is_none_or(|p| (0.0..=100.0).contains(&p)), "autotune", "must be a percentile (0-100)"),
+		(args.output == "JSON" || args.output == "DEBUG", "output", "must be JSON or DEBUG"),
+	];
+
+	match checks.iter().find(|(ok, key, _)| !ok && settings.contains_key(*key)) {
+		Some((_, key, problem)) => Err(format!("{}.{}: {}", at, key, problem)),
+		None => Ok(()),
+	}
+}
+
+fn value<T: DeserializeOwned>(v: &Value, key: &str) -> Result<T, String>
+{
+	T::deserialize(v).map_err(|e| format!("{}: {}", key, e))
+}
+
+// Settings written as on the command line, e.g. "315-45,90-135", or as
+// reported by get().
+fn parsed<T: DeserializeOwned, F: Fn(&str) -> Result<T, String>>(v: &Value, key: &str, parse: F) -> Result<T, String>
+{
+	match v {
+		Value::String(s) => parse(s).map_err(|e| format!("{}: {}", key, e)),
+		_ => value(v, key),
+	}
+}
+
+fn inline_or_file<T: DeserializeOwned>(v: &Value, key: &str, load: fn(&str) -> Result<T, String>) -> Result<T, String>
+{
+	match v {
+		Value::String(path) => load(path).map_err(|e| format!("{}: {}", key, e)),
+		_ => value(v, key),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::json;
+
+	fn args() -> AppArgs {
+		crate::parse_args_from(pico_args::Arguments::from_vec(vec![])).unwrap()
+	}
+
+	#[test]
+	fn paths_can_not_be_set() {
+		for key in ["config", "input", "recorddir", "snapshotdir", "heatmapfile", "debugdir"] {
+			let result = set(&args(), key, &json!("/etc/passwd"));
+			assert_eq!(result.err(), Some(format!("{}: paths can not be set over the control socket", key)));
+		}
+	}
+
+	#[test]
+	fn files_can_not_be_named() {
+		for key in ["zones", "lines", "classes"] {
+			assert!(set(&args(), key, &json!("/etc/passwd")).is_err(), "{}", key);
+		}
+
+		let zones = set(&args(), "zones", &json!([])).unwrap();
+		assert!(zones.zones.is_empty());
+	}
+
+	#[test]
+	fn fixed_and_global_settings_can_not_be_set() {
+		for key in ["width", "clock", "framerate", "port", "listen", "metricslisten", "controllisten"] {
+			assert!(set(&args(), key, &json!(1)).is_err(), "{}", key);
+		}
+	}
+
+	#[test]
+	fn ordinary_settings_can_be_set() {
+		let args = set(&args(), "sadthreshold", &json!(300)).unwrap();
+		assert_eq!(args.sadthreshold, 300);
+
+		let args = set(&args, "minmagnitude", &json!(3.5)).unwrap();
+		assert_eq!((args.sadthreshold, args.minmagnitude), (300, 3.5));
+
+		assert!(set(&args, "sadthreshold", &json!("lots")).is_err());
+		assert!(set(&args, "epsilon", &json!(0)).is_err());
+		assert!(set(&args, "nosuchsetting", &json!(1)).is_err());
+	}
+}
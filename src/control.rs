@@ -0,0 +1,186 @@
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::spawn;
+use std::time::Duration;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+// Runtime control over a line based JSON socket, to tune cameras live.
+//
+// Every line is a request, answered by a single line:
+//
+//	{"command":"cameras"}										-> {"ok":true,"value":["garden"]}
+//	{"camera":"garden","command":"get"}							-> all settings
+//	{"camera":"garden","command":"get","key":"sadthreshold"}	-> {"ok":true,"camera":"garden","value":250}
+//	{"camera":"garden","command":"set","key":"sadthreshold","value":300}
+//	{"camera":"garden","command":"reset"}						clear history
+//	{"camera":"garden","command":"pause"}						stop processing (frames are dropped)
+//	{"camera":"garden","command":"resume"}
+//	{"camera":"garden","command":"snapshot"}					tracks and vector grid, see snapshot.rs
+//	{"camera":"garden","command":"heatmap"}						heatmap now, also written to --heatmapfile
+//
+// "camera" may be left out while only one camera is connected. Errors are
+// answered with {"ok":false,"error":"..."}. Settings are named as in the
+// configuration file. Changes are announced in the output of the camera, so
+// that everyone watching it sees them.
+//
+// There is no authentication, so the socket is only on 127.0.0.1 unless told
+// otherwise (--controllisten), and settings that are paths can not be set.
+//
+// Requests are handled by the processor of the camera, between frames.
+
+#[derive(Deserialize)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum Command {
+	Cameras,
+	Get {
+		#[serde(default)]
+		key: Option<String>,
+	},
+	Set {
+		key: String,
+		value: Value,
+	},
+	Reset,
+	Pause,
+	Resume,
+	Snapshot,
+	Heatmap,
+}
+
+#[derive(Deserialize)]
+struct RequestLine {
+	#[serde(default)]
+	camera: Option<String>,
+	#[serde(flatten)]
+	command: Command,
+}
+
+pub struct Request {
+	pub command: Command,
+	pub reply: Sender<Result<Value, String>>,
+}
+
+// How long to wait for a camera to handle a request; it only does so between
+// frames.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+pub struct Control {
+	cameras: Mutex<BTreeMap<String, (u64, Sender<Request>)>>,
+	registrations: Mutex<u64>,
+}
+
+// Handed to a processor; unregisters its camera when dropped.
+pub struct Registration {
+	control: Arc<Control>,
+	camera: String,
+	id: u64,
+	pub requests: Receiver<Request>,
+}
+
+impl Drop for Registration {
+	fn drop(&mut self) {
+		let mut cameras = self.control.cameras.lock().unwrap();
+		if cameras.get(&self.camera).is_some_and(|(id, _)| *id == self.id) {
+			cameras.remove(&self.camera);
+		}
+	}
+}
+
+impl Control {
+	// A camera connected. Should another connection claim the same camera,
+	// requests go to the latest one.
+	pub fn register(self: &Arc<Self>, camera: &str) -> Registration
+	{
+		let (send, requests) = channel();
+
+		let id = {
+			let mut registrations = self.registrations.lock().unwrap();
+			*registrations += 1;
+			*registrations
+		};
+
+		self.cameras.lock().unwrap().insert(camera.to_string(), (id, send));
+
+		Registration {
+			control: self.clone(),
+			camera: camera.to_string(),
+			id,
+			requests,
+		}
+	}
+
+	fn handle(&self, line: &str) -> Value
+	{
+		let request: RequestLine = match serde_json::from_str(line) {
+			Ok(r) => r,
+			Err(e) => return json!({ "ok": false, "error": e.to_string() }),
+		};
+
+		let cameras = self.cameras.lock().unwrap();
+
+		if let Command::Cameras = request.command {
+			return json!({ "ok": true, "value": cameras.keys().collect::<Vec<_>>() });
+		}
+
+		let camera = match request.camera {
+			Some(c) => c,
+			None if cameras.len() == 1 => cameras.keys().next().unwrap().clone(),
+			None => return json!({ "ok": false, "error": "camera not given" }),
+		};
+
+		let (reply, answer) = channel();
+		let sent = cameras.get(&camera).map(|(_, send)| send.send(Request { command: request.command, reply }));
+		drop(cameras);
+
+		match sent {
+			Some(Ok(())) => match answer.recv_timeout(REPLY_TIMEOUT) {
+				Ok(Ok(value)) => json!({ "ok": true, "camera": camera, "value": value }),
+				Ok(Err(e)) => json!({ "ok": false, "camera": camera, "error": e }),
+				Err(_) => json!({ "ok": false, "camera": camera, "error": "camera is not responding" }),
+			},
+			_ => json!({ "ok": false, "camera": camera, "error": "camera is not connected" }),
+		}
+	}
+}
+
+// Accepts control connections on 'addr'.
+pub fn serve(addr: String, control: Arc<Control>)
+{
+	let listener = match TcpListener::bind(&addr) {
+		Ok(l) => l,
+		Err(e) => {
+			eprintln!("Could not listen for control on {}: {}", addr, e);
+			return;
+		}
+	};
+
+	spawn(move || {
+		for stream in listener.incoming().flatten() {
+			let control = control.clone();
+
+			spawn(move || {
+				let mut out = &stream;
+
+				for line in BufReader::new(&stream).lines() {
+					let line = match line {
+						Ok(l) => l,
+						Err(_) => break,
+					};
+
+					if line.trim().is_empty() {
+						continue;
+					}
+
+					if writeln!(out, "{}", control.handle(&line)).is_err() {
+						break;
+					}
+				}
+			});
+		}
+	});
+}
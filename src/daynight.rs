@@ -0,0 +1,180 @@
+// Day/night switching.
+//
+// At night the sensor gain goes up and so does the SAD noise; cameras want
+// different settings then. Whether it is night is decided either by schedule
+// (local time, given as an offset from UTC as we have no time zone database)
+// or by the noise floor: the mean SAD of a frame, smoothed, going above one
+// level means night, below a lower one means day. The gap between the levels
+// and a minimum time between switches keep it from flapping at dusk.
+//
+// What settings apply is up to the caller (see --dayprofile, --nightprofile).
+// A switch only counts once the caller applied it; if it could not, it is
+// tried again a while later. At first it is neither day nor night, so the
+// first update always asks for a switch and the settings for the time of day
+// apply from the start.
+
+pub struct DayNight {
+	night: Option<bool>,
+	noise: Option<f32>,
+	last_switch: Option<u128>,
+	retry_at: u128,
+}
+
+const NOISE_WEIGHT: f32 = 0.01;
+const MIN_HOLD_MS: u128 = 60000;
+const RETRY_MS: u128 = 60000;
+const MINUTES_PER_DAY: i64 = 24 * 60;
+
+impl DayNight {
+	pub fn new() -> DayNight {
+		DayNight {
+			night: None,
+			noise: None,
+			last_switch: None,
+			retry_at: 0,
+		}
+	}
+
+	// 'schedule': night from/to, in minutes after midnight
+	// 'levels': mean SAD at which night starts/ends
+	// 'now': ms since epoch
+	// Returns whether it should be night from now on, if that changed; see
+	// switched() and failed().
+	pub fn update(&mut self, schedule: Option<(u32, u32)>, levels: Option<(f32, f32)>, utc_offset: i32, now: u128, mean_sad: f32) -> Option<bool>
+	{
+		let noise = match self.noise {
+			Some(n) => n + (mean_sad - n) * NOISE_WEIGHT,
+			None => mean_sad,
+		};
+		self.noise = Some(noise);
+
+		let night = if let Some((from, to)) = schedule {
+			let minute = ((now / 60000) as i64 + utc_offset as i64).rem_euclid(MINUTES_PER_DAY) as u32;
+			if from <= to {
+				minute >= from && minute < to
+			} else {
+				minute >= from || minute < to
+			}
+		} else if let Some((enter, leave)) = levels {
+			let held = self.last_switch.is_none_or(|t| now.saturating_sub(t) >= MIN_HOLD_MS);
+
+			match self.night {
+				Some(true) => !(held && noise < leave),
+				_ => held && noise > enter,
+			}
+		} else {
+			false
+		};
+
+		if Some(night) == self.night || now < self.retry_at {
+			return None;
+		}
+
+		Some(night)
+	}
+
+	// The switch update() asked for was made.
+	pub fn switched(&mut self, night: bool, now: u128) {
+		self.night = Some(night);
+		self.last_switch = Some(now);
+	}
+
+	// The switch update() asked for could not be made; try again later.
+	pub fn failed(&mut self, now: u128) {
+		self.retry_at = now + RETRY_MS;
+	}
+}
+
+// Parses "20:30-06:00" into minutes after midnight.
+pub fn parse_schedule(s: &str) -> Result<(u32, u32), String>
+{
+	let (from, to) = s.split_once('-').ok_or(format!("'{}' is not HH:MM-HH:MM", s))?;
+
+	Ok((parse_time(from.trim())?, parse_time(to.trim())?))
+}
+
+fn parse_time(s: &str) -> Result<u32, String>
+{
+	let (h, m) = s.split_once(':').ok_or(format!("'{}' is not HH:MM", s))?;
+	let h: u32 = h.parse().map_err(|e| format!("'{}': {}", s, e))?;
+	let m: u32 = m.parse().map_err(|e| format!("'{}': {}", s, e))?;
+
+	if h > 23 || m > 59 {
+		return Err(format!("'{}' is not a time of day", s));
+	}
+
+	Ok(h * 60 + m)
+}
+
+// Parses "ENTER:LEAVE", e.g. "400:300".
+pub fn parse_levels(s: &str) -> Result<(f32, f32), String>
+{
+	let (enter, leave) = s.split_once(':').ok_or(format!("'{}' is not ENTER:LEAVE", s))?;
+	let enter: f32 = enter.trim().parse().map_err(|e| format!("'{}': {}", s, e))?;
+	let leave: f32 = leave.trim().parse().map_err(|e| format!("'{}': {}", s, e))?;
+
+	if leave > enter {
+		return Err(format!("'{}': night has to start at a higher level than it ends", s));
+	}
+
+	Ok((enter, leave))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn schedule_is_parsed() {
+		assert_eq!(parse_schedule("20:30-06:00"), Ok((1230, 360)));
+		assert!(parse_schedule("20:30").is_err());
+		assert!(parse_schedule("24:00-06:00").is_err());
+		assert!(parse_schedule("20:60-06:00").is_err());
+	}
+
+	#[test]
+	fn levels_are_parsed() {
+		assert_eq!(parse_levels("400:300"), Ok((400.0, 300.0)));
+		assert!(parse_levels("300:400").is_err());
+		assert!(parse_levels("400").is_err());
+	}
+
+	#[test]
+	fn first_update_switches() {
+		let mut daynight = DayNight::new();
+		assert_eq!(daynight.update(None, None, 0, 0, 0.0), Some(false));
+		daynight.switched(false, 0);
+		assert_eq!(daynight.update(None, None, 0, 1000, 0.0), None);
+
+		let mut daynight = DayNight::new();
+		assert_eq!(daynight.update(None, Some((400.0, 300.0)), 0, 0, 500.0), Some(true));
+	}
+
+	#[test]
+	fn noise_switches_after_holding() {
+		let mut daynight = DayNight::new();
+		let levels = Some((400.0, 300.0));
+
+		assert_eq!(daynight.update(None, levels, 0, 0, 100.0), Some(false));
+		daynight.switched(false, 0);
+
+		// Noisy, but too soon after the last switch.
+		for t in 1..10 {
+			assert_eq!(daynight.update(None, levels, 0, t * 1000, 100000.0), None);
+		}
+		assert_eq!(daynight.update(None, levels, 0, MIN_HOLD_MS, 100000.0), Some(true));
+	}
+
+	#[test]
+	fn failed_switches_are_retried() {
+		let mut daynight = DayNight::new();
+		let night = Some((0, 24 * 60 - 1));
+
+		assert_eq!(daynight.update(night, None, 0, 0, 0.0), Some(true));
+		daynight.failed(0);
+		assert_eq!(daynight.update(night, None, 0, 1000, 0.0), None);
+		assert_eq!(daynight.update(night, None, 0, RETRY_MS, 0.0), Some(true));
+		daynight.switched(true, RETRY_MS);
+		assert_eq!(daynight.update(night, None, 0, RETRY_MS + 1000, 0.0), None);
+	}
+}
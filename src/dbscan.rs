@@ -2,12 +2,15 @@ use crate::mvrprocessor::MotionVector;
 
 pub struct DbScan<'a> {
 	pub epsilon: f32,
+	// Epsilon per row (y) of the data, for perspective; if empty, 'epsilon'
+	// is used everywhere.
+	pub row_epsilon: &'a [f32],
 	pub min_points: usize,
 	pub data: &'a Vec<MotionVector>,
 	pub results: &'a mut Vec<usize>,
 }
 
-impl<'a> DbScan<'_> {
+impl DbScan<'_> {
 	pub fn run(&mut self)
 	{
 		let mut next_ix: usize = 0;
@@ -35,6 +38,7 @@ impl<'a> DbScan<'_> {
 		let neighbours: &mut Vec<usize> = &mut vec![0_usize; 0];
 
 		let point: &MotionVector = &self.data[point_ix];
+		let epsilon = self.epsilon_at(point);
 		let mut v;
 
 		for i in 0..self.data.len() {
@@ -48,8 +52,8 @@ impl<'a> DbScan<'_> {
 			// execution time down to 50% (and more in quiet scenarios). It
 			// also makes execution time a little more predictable. The (big)
 			// downside is that it makes epsilon mean something else.
-			if (v.x - point.x).abs() < self.epsilon as i16 && 
-			   self.manhattan_distance(v, point) <= self.epsilon {
+			if (v.x - point.x).abs() < epsilon as i16 && 
+			   self.manhattan_distance(v, point) <= epsilon {
 				neighbours.push(i);
 			}
 		}
@@ -57,18 +61,14 @@ impl<'a> DbScan<'_> {
 		neighbours.to_vec()
 	}
 
-	fn expand(&mut self, point_ix: usize, neighbours: &mut Vec<usize>, cluster_ix: usize) {
+	fn expand(&mut self, point_ix: usize, neighbours: &mut [usize], cluster_ix: usize) {
 
 		// Assign cluster id (which is just an index)
 		self.results[point_ix] = cluster_ix;
 
 		let mut curr_neighbours: Vec<usize>;
 
-		let mut curr_point_ix;
-
-		for i in 0..neighbours.len() {
-			curr_point_ix = neighbours[i];
-
+		for &curr_point_ix in neighbours.iter() {
 			if self.results[curr_point_ix] == 0xffff {
 				// Default: Point visited and marked as noise
 				self.results[curr_point_ix] = 0;
@@ -86,6 +86,13 @@ impl<'a> DbScan<'_> {
 		}
 	}
 
+	fn epsilon_at(&self, point: &MotionVector) -> f32 {
+		match self.row_epsilon.get(point.y as usize) {
+			Some(epsilon) => *epsilon,
+			None => self.epsilon,
+		}
+	}
+
 	// TODO: Consider using some kind of a SIMD version? How to do it in Rust?
 	// Note: As it is right now, I am actually quite fine with using manhattan distance, 
 	//       which would not benefit awesomely from SIMD.
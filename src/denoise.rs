@@ -0,0 +1,209 @@
+use crate::mvrprocessor::MotionVector;
+
+// Spatial clean-up of a frame before candidates are picked.
+//
+// Raw H.264 vectors have isolated outliers and zero-vector holes in the middle
+// of moving objects. Two (optional) stages deal with that:
+// - a vector median filter over the full grid; every vector is replaced by the
+//   vector in its neighbourhood that is closest to all the others
+// - a morphological open (removes specks) and close (fills holes) on the mask
+//   of active blocks
+//
+// Kernel sizes are the width of a square window in blocks; 0 or 1 disables a
+// stage. Even sizes are rounded up.
+
+pub struct Denoiser {
+	width: usize,
+	height: usize,
+	median_radius: usize,
+	open_radius: usize,
+	close_radius: usize,
+	field: Vec<(i8, i8)>,
+	window: Vec<(i8, i8)>,
+	scratch: Vec<bool>,
+}
+
+impl Denoiser {
+	pub fn new(width: usize, height: usize, median_kernel: usize, open_kernel: usize, close_kernel: usize) -> Denoiser {
+		Denoiser {
+			width,
+			height,
+			median_radius: median_kernel / 2,
+			open_radius: open_kernel / 2,
+			close_radius: close_kernel / 2,
+			field: vec![(0, 0); width * height],
+			window: vec![],
+			scratch: vec![false; width * height],
+		}
+	}
+
+	pub fn filters_vectors(&self) -> bool {
+		self.median_radius > 0
+	}
+
+	pub fn filters_mask(&self) -> bool {
+		self.open_radius > 0 || self.close_radius > 0
+	}
+
+	// Only touches dx/dy; caller is expected to (re)calculate dir/mag.
+	pub fn filter_vectors(&mut self, vectors: &mut [MotionVector])
+	{
+		if !self.filters_vectors() {
+			return;
+		}
+
+		for (field, v) in self.field.iter_mut().zip(vectors.iter()) {
+			*field = (v.dx, v.dy);
+		}
+
+		let r = self.median_radius;
+
+		for y in 0..self.height {
+			for x in 0..self.width {
+				self.window.clear();
+
+				for ny in y.saturating_sub(r)..=(y + r).min(self.height - 1) {
+					for nx in x.saturating_sub(r)..=(x + r).min(self.width - 1) {
+						self.window.push(self.field[ny * self.width + nx]);
+					}
+				}
+
+				let (dx, dy) = vector_median(&self.window);
+				vectors[y * self.width + x].dx = dx;
+				vectors[y * self.width + x].dy = dy;
+			}
+		}
+	}
+
+	pub fn filter_mask(&mut self, mask: &mut [bool])
+	{
+		if self.open_radius > 0 {
+			self.erode(mask, self.open_radius);
+			self.dilate(mask, self.open_radius);
+		}
+
+		if self.close_radius > 0 {
+			self.dilate(mask, self.close_radius);
+			self.erode(mask, self.close_radius);
+		}
+	}
+
+	fn erode(&mut self, mask: &mut [bool], r: usize)
+	{
+		self.morph(mask, r, true);
+	}
+
+	fn dilate(&mut self, mask: &mut [bool], r: usize)
+	{
+		self.morph(mask, r, false);
+	}
+
+	// Erosion: a block stays active only if its whole window is active.
+	// Dilation: a block becomes active if anything in its window is active.
+	// Outside of the grid does not count either way.
+	fn morph(&mut self, mask: &mut [bool], r: usize, erode: bool)
+	{
+		self.scratch.copy_from_slice(mask);
+
+		for y in 0..self.height {
+			for x in 0..self.width {
+				let mut result = erode;
+
+				'window: for ny in y.saturating_sub(r)..=(y + r).min(self.height - 1) {
+					for nx in x.saturating_sub(r)..=(x + r).min(self.width - 1) {
+						if self.scratch[ny * self.width + nx] != erode {
+							result = !erode;
+							break 'window;
+						}
+					}
+				}
+
+				mask[y * self.width + x] = result;
+			}
+		}
+	}
+}
+
+// The vector in the window with the smallest summed (manhattan) distance to all
+// other vectors in the window.
+fn vector_median(window: &[(i8, i8)]) -> (i8, i8)
+{
+	let mut best = window[0];
+	let mut best_distance = i32::MAX;
+
+	for a in window {
+		let mut distance: i32 = 0;
+
+		for b in window {
+			distance += (a.0 as i32 - b.0 as i32).abs() + (a.1 as i32 - b.1 as i32).abs();
+		}
+
+		if distance < best_distance {
+			best_distance = distance;
+			best = *a;
+		}
+	}
+
+	best
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// A 'size' by 'size' mask with the blocks in 'active' active.
+	fn mask(size: usize, active: &[(usize, usize)]) -> Vec<bool> {
+		let mut mask = vec![false; size * size];
+		for (x, y) in active {
+			mask[y * size + x] = true;
+		}
+		mask
+	}
+
+	#[test]
+	fn median_ignores_an_outlier() {
+		let window = [(2, 1), (2, 1), (3, 1), (-60, 50), (2, 2)];
+		assert_eq!(vector_median(&window), (2, 1));
+	}
+
+	#[test]
+	fn isolated_vector_is_replaced() {
+		let mut denoiser = Denoiser::new(5, 5, 3, 0, 0);
+		let mut vectors = vec![MotionVector::new(); 25];
+		vectors[12].dx = 40;
+		vectors[12].dy = -40;
+
+		denoiser.filter_vectors(&mut vectors);
+		assert_eq!((vectors[12].dx, vectors[12].dy), (0, 0));
+	}
+
+	#[test]
+	fn open_removes_a_speck() {
+		let mut denoiser = Denoiser::new(7, 7, 0, 3, 0);
+		let mut m = mask(7, &[(3, 3)]);
+
+		denoiser.filter_mask(&mut m);
+		assert!(m.iter().all(|a| !a));
+	}
+
+	#[test]
+	fn open_keeps_a_solid_block() {
+		let mut denoiser = Denoiser::new(7, 7, 0, 3, 0);
+		let block: Vec<_> = (2..5).flat_map(|y| (2..5).map(move |x| (x, y))).collect();
+		let mut m = mask(7, &block);
+		let expected = m.clone();
+
+		denoiser.filter_mask(&mut m);
+		assert_eq!(m, expected);
+	}
+
+	#[test]
+	fn close_fills_a_hole() {
+		let mut denoiser = Denoiser::new(7, 7, 0, 0, 3);
+		let ring: Vec<_> = (2..5).flat_map(|y| (2..5).map(move |x| (x, y))).filter(|&b| b != (3, 3)).collect();
+		let mut m = mask(7, &ring);
+
+		denoiser.filter_mask(&mut m);
+		assert!(m[3 * 7 + 3]);
+	}
+}
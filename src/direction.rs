@@ -0,0 +1,87 @@
+use crate::mvrprocessor::Cluster;
+use crate::zones::{contains, within_range, Zone};
+
+// Direction filter on cluster level.
+//
+// Some cameras should only care about motion in some directions (towards the
+// door, not the cars passing on the street). Clusters that move in a direction
+// that is not allowed, or that move too slowly, are tagged as 'disallowed'.
+// They are still reported (it is useful to see them when tuning) but do not
+// raise alarms.
+//
+// Allowed directions can be set globally and per zone. A cluster must be
+// allowed globally and by every zone its centre is in. An empty list of ranges
+// allows everything.
+
+pub struct DirectionFilter {
+	ranges: Vec<(f32, f32)>,
+	min_magnitude: f32,
+	zones: Vec<Zone>,
+}
+
+impl DirectionFilter {
+	pub fn new(ranges: Vec<(f32, f32)>, min_magnitude: f32, zones: &[Zone]) -> DirectionFilter {
+		DirectionFilter {
+			ranges,
+			min_magnitude,
+			zones: zones.iter()
+				.filter(|z| !z.allowed_directions.is_empty() || z.min_magnitude > 0.0)
+				.cloned()
+				.collect(),
+		}
+	}
+
+	pub fn allows(&self, c: &Cluster) -> bool
+	{
+		if !allowed(c, &self.ranges, self.min_magnitude) {
+			return false;
+		}
+
+		let (x, y) = c.centre();
+
+		self.zones.iter()
+			.filter(|z| contains(&z.polygon, x, y))
+			.all(|z| allowed(c, &z.allowed_directions, z.min_magnitude))
+	}
+}
+
+fn allowed(c: &Cluster, ranges: &[(f32, f32)], min_magnitude: f32) -> bool
+{
+	c.mag >= min_magnitude
+		&& (ranges.is_empty() || ranges.iter().any(|r| within_range(c.dir, r.0, r.1)))
+}
+
+// Parses ranges in degrees, such as "315-45,90-135".
+pub fn parse_ranges(s: &str) -> Result<Vec<(f32, f32)>, String>
+{
+	s.split(',')
+		.map(|range| {
+			let (from, to) = range.split_once('-').ok_or(format!("'{}' is not a range", range))?;
+			Ok((
+				from.trim().parse::<f32>().map_err(|e| format!("'{}': {}", range, e))?,
+				to.trim().parse::<f32>().map_err(|e| format!("'{}': {}", range, e))?,
+			))
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn ranges_are_parsed() {
+		assert_eq!(parse_ranges("315-45, 90-135"), Ok(vec![(315.0, 45.0), (90.0, 135.0)]));
+		assert!(parse_ranges("315").is_err());
+		assert!(parse_ranges("a-45").is_err());
+	}
+
+	#[test]
+	fn rightward_motion_is_allowed_across_zero() {
+		let filter = DirectionFilter::new(vec![(315.0, 45.0)], 0.0, &[]);
+
+		assert!(filter.allows(&Cluster { dir: 359.0, mag: 5.0, ..Cluster::new() }));
+		assert!(filter.allows(&Cluster { dir: 1.0, mag: 5.0, ..Cluster::new() }));
+		assert!(!filter.allows(&Cluster { dir: 180.0, mag: 5.0, ..Cluster::new() }));
+	}
+}
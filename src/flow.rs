@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+use crate::mvrprocessor::MotionVector;
+
+// Dominant flow per region.
+//
+// For traffic and the like, the average flow in a region says more than blobs
+// do. The vector grid is divided into a coarse grid of tiles and, over a number
+// of frames, we average per tile the motion of the active blocks (dx, dy and
+// magnitude) and how large a part of the tile was active.
+
+pub struct FlowField {
+	width: usize,
+	height: usize,
+	cols: usize,
+	rows: usize,
+	frames: usize,
+	dx: Vec<f32>,
+	dy: Vec<f32>,
+	mag: Vec<f32>,
+	active: Vec<usize>,
+	blocks: Vec<usize>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FlowMessage {
+	pub flow: FlowData,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FlowData {
+	pub cols: usize,
+	pub rows: usize,
+	pub frames: usize,
+	pub tiles: Vec<FlowTile>,	// row by row
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FlowTile {
+	pub dx: f32,		// mean over active blocks
+	pub dy: f32,		// mean over active blocks
+	pub mag: f32,		// mean over active blocks
+	pub active: f32,	// ratio of blocks that were active
+}
+
+impl FlowField {
+	pub fn new(width: usize, height: usize, cols: usize, rows: usize) -> FlowField {
+		let cols = cols.clamp(1, width);
+		let rows = rows.clamp(1, height);
+
+		FlowField {
+			width,
+			height,
+			cols,
+			rows,
+			frames: 0,
+			dx: vec![0.0; cols * rows],
+			dy: vec![0.0; cols * rows],
+			mag: vec![0.0; cols * rows],
+			active: vec![0; cols * rows],
+			blocks: vec![0; cols * rows],
+		}
+	}
+
+	pub fn add(&mut self, vectors: &[MotionVector], active: &[bool])
+	{
+		for i in 0..vectors.len() {
+			let tile = self.tile_of(i);
+
+			self.blocks[tile] += 1;
+
+			if active[i] {
+				self.active[tile] += 1;
+				self.dx[tile] += vectors[i].dx as f32;
+				self.dy[tile] += vectors[i].dy as f32;
+				self.mag[tile] += vectors[i].mag;
+			}
+		}
+
+		self.frames += 1;
+	}
+
+	// Summary since last call.
+	pub fn take(&mut self) -> FlowMessage
+	{
+		let tiles = (0..self.cols * self.rows)
+			.map(|t| {
+				let active = self.active[t].max(1) as f32;
+				FlowTile {
+					dx: self.dx[t] / active,
+					dy: self.dy[t] / active,
+					mag: self.mag[t] / active,
+					active: self.active[t] as f32 / self.blocks[t].max(1) as f32,
+				}
+			})
+			.collect();
+
+		let msg = FlowMessage {
+			flow: FlowData {
+				cols: self.cols,
+				rows: self.rows,
+				frames: self.frames,
+				tiles,
+			}
+		};
+
+		self.frames = 0;
+		self.dx.iter_mut().for_each(|v| *v = 0.0);
+		self.dy.iter_mut().for_each(|v| *v = 0.0);
+		self.mag.iter_mut().for_each(|v| *v = 0.0);
+		self.active.iter_mut().for_each(|v| *v = 0);
+		self.blocks.iter_mut().for_each(|v| *v = 0);
+
+		msg
+	}
+
+	fn tile_of(&self, index: usize) -> usize
+	{
+		let x = index % self.width;
+		let y = index / self.width;
+
+		(y * self.rows / self.height) * self.cols + (x * self.cols / self.width)
+	}
+}
+
+// Parses a grid size such as "8x8" into (cols, rows).
+pub fn parse_grid(s: &str) -> Result<(usize, usize), String>
+{
+	let (cols, rows) = s.split_once('x').ok_or(format!("'{}' is not COLSxROWS", s))?;
+
+	Ok((
+		cols.trim().parse().map_err(|e| format!("'{}': {}", s, e))?,
+		rows.trim().parse().map_err(|e| format!("'{}': {}", s, e))?,
+	))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn grid_is_parsed() {
+		assert_eq!(parse_grid("8x6"), Ok((8, 6)));
+		assert!(parse_grid("8").is_err());
+		assert!(parse_grid("8x-1").is_err());
+	}
+}
@@ -0,0 +1,103 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use serde::{Deserialize, Serialize};
+use crate::mvrprocessor::MotionVector;
+
+// Activity heatmap: per macroblock, how often it was active (candidate count)
+// and how much it moved (summed magnitude), decaying over time so that it
+// reflects roughly the last 'half life' worth of activity.
+//
+// Useful to see where things happen over a day and to draw better ignore
+// zones. Exported as PGM (8-bit greyscale of candidate counts, normalised to
+// the busiest block) or CSV (x,y,count,magnitude), by file extension. PNG is
+// deliberately not supported; it would drag in a dependency for little gain.
+
+pub struct Heatmap {
+	width: usize,
+	height: usize,
+	half_life: f32,
+	counts: Vec<f32>,
+	magnitudes: Vec<f32>,
+	last: Option<u128>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct HeatmapMessage {
+	pub heatmap: HeatmapData,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct HeatmapData {
+	pub width: usize,
+	pub height: usize,
+	pub counts: Vec<u32>,		// rounded
+	pub magnitudes: Vec<u32>,	// rounded
+}
+
+impl Heatmap {
+	// 'half_life' in ms; 0 means no decay at all.
+	pub fn new(width: usize, height: usize, half_life: u32) -> Heatmap {
+		Heatmap {
+			width,
+			height,
+			half_life: half_life as f32,
+			counts: vec![0.0; width * height],
+			magnitudes: vec![0.0; width * height],
+			last: None,
+		}
+	}
+
+	pub fn set_half_life(&mut self, half_life: u32) {
+		self.half_life = half_life as f32;
+	}
+
+	pub fn add(&mut self, candidates: &[MotionVector], now: u128)
+	{
+		if let Some(last) = self.last {
+			if self.half_life > 0.0 && now > last {
+				let decay = 0.5_f32.powf((now - last) as f32 / self.half_life);
+				self.counts.iter_mut().for_each(|v| *v *= decay);
+				self.magnitudes.iter_mut().for_each(|v| *v *= decay);
+			}
+		}
+		self.last = Some(now);
+
+		for c in candidates {
+			let index = c.y as usize * self.width + c.x as usize;
+			self.counts[index] += 1.0;
+			self.magnitudes[index] += c.mag;
+		}
+	}
+
+	pub fn message(&self) -> HeatmapMessage
+	{
+		HeatmapMessage {
+			heatmap: HeatmapData {
+				width: self.width,
+				height: self.height,
+				counts: self.counts.iter().map(|v| v.round() as u32).collect(),
+				magnitudes: self.magnitudes.iter().map(|v| v.round() as u32).collect(),
+			}
+		}
+	}
+
+	pub fn export(&self, path: &str) -> std::io::Result<()>
+	{
+		let mut out = BufWriter::new(File::create(path)?);
+
+		if path.to_lowercase().ends_with(".csv") {
+			writeln!(out, "x,y,count,magnitude")?;
+			for i in 0..self.counts.len() {
+				writeln!(out, "{},{},{:.2},{:.2}", i % self.width, i / self.width, self.counts[i], self.magnitudes[i])?;
+			}
+		} else {
+			let max = self.counts.iter().cloned().fold(0.0, f32::max);
+			let scale = if max > 0.0 { 255.0 / max } else { 0.0 };
+
+			write!(out, "P5\n{} {}\n255\n", self.width, self.height)?;
+			out.write_all(&self.counts.iter().map(|v| (v * scale) as u8).collect::<Vec<u8>>())?;
+		}
+
+		out.flush()
+	}
+}
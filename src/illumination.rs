@@ -0,0 +1,134 @@
+// Detects scene-wide illumination changes: lights switching on or off, clouds
+// passing in front of the sun, the IR-cut filter flipping. The encoder then
+// reports high SAD in nearly every block and we would end up with one giant
+// cluster (and a false alert).
+//
+// We keep a rolling baseline (exponential moving average) of the number of
+// candidates and the mean SAD of the frame. A frame is flagged when enough of
+// the frame is active _and_ either of the two numbers is way above baseline.
+
+// Number of frames to learn from before we start flagging anything.
+const LEARN_FRAMES: usize = 10;
+
+// How much weight a new frame has in the rolling baseline.
+const BASELINE_WEIGHT: f32 = 0.05;
+
+// If we keep seeing 'spikes' for this many frames in a row, it is not a spike,
+// it is the new normal. Start learning again.
+const MAX_SPIKE_FRAMES: usize = 30;
+
+pub struct IlluminationDetector {
+	baseline_candidates: f32,
+	baseline_sad: f32,
+	learned_frames: usize,
+	spike_frames: usize,
+}
+
+impl IlluminationDetector {
+	pub fn new() -> IlluminationDetector {
+		IlluminationDetector {
+			baseline_candidates: 0.0,
+			baseline_sad: 0.0,
+			learned_frames: 0,
+			spike_frames: 0,
+		}
+	}
+
+	// Returns true if this frame looks like a scene-wide change. Frames that
+	// are flagged do not contribute to the baseline (until it is clear that
+	// the scene really changed).
+	pub fn is_spike(&mut self, candidates: usize, vector_count: usize, mean_sad: f32, min_coverage: f32, factor: f32) -> bool
+	{
+		let coverage = candidates as f32 / vector_count as f32;
+
+		let spike = self.learned_frames >= LEARN_FRAMES
+			&& coverage >= min_coverage
+			&& (
+				candidates as f32 > self.baseline_candidates * factor
+				|| mean_sad > self.baseline_sad * factor
+			);
+
+		if spike {
+			self.spike_frames += 1;
+			if self.spike_frames <= MAX_SPIKE_FRAMES {
+				return true;
+			}
+		} else {
+			self.spike_frames = 0;
+		}
+
+		self.learn(candidates as f32, mean_sad);
+
+		false
+	}
+
+	fn learn(&mut self, candidates: f32, mean_sad: f32)
+	{
+		if self.learned_frames == 0 {
+			self.baseline_candidates = candidates;
+			self.baseline_sad = mean_sad;
+		} else {
+			self.baseline_candidates += (candidates - self.baseline_candidates) * BASELINE_WEIGHT;
+			self.baseline_sad += (mean_sad - self.baseline_sad) * BASELINE_WEIGHT;
+		}
+
+		self.learned_frames += 1;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const BLOCKS: usize = 1000;
+
+	fn learned() -> IlluminationDetector {
+		let mut detector = IlluminationDetector::new();
+		for _ in 0..LEARN_FRAMES {
+			assert!(!detector.is_spike(20, BLOCKS, 100.0, 0.25, 3.0));
+		}
+		detector
+	}
+
+	#[test]
+	fn nothing_is_flagged_while_learning() {
+		let mut detector = IlluminationDetector::new();
+
+		assert!(!detector.is_spike(20, BLOCKS, 100.0, 0.25, 3.0));
+		assert!(!detector.is_spike(900, BLOCKS, 2000.0, 0.25, 3.0));
+	}
+
+	#[test]
+	fn scene_wide_change_is_flagged() {
+		let mut detector = learned();
+
+		assert!(detector.is_spike(900, BLOCKS, 2000.0, 0.25, 3.0));
+		// Mean SAD alone is enough, as long as enough of the scene is active.
+		assert!(detector.is_spike(300, BLOCKS, 2000.0, 0.25, 3.0));
+	}
+
+	#[test]
+	fn normal_noise_is_not_flagged() {
+		let mut detector = learned();
+
+		assert!(!detector.is_spike(40, BLOCKS, 150.0, 0.25, 3.0));
+		// Plenty going on, but not above baseline by the factor.
+		let mut busy = IlluminationDetector::new();
+		for _ in 0..LEARN_FRAMES {
+			busy.is_spike(400, BLOCKS, 500.0, 0.25, 3.0);
+		}
+		assert!(!busy.is_spike(500, BLOCKS, 600.0, 0.25, 3.0));
+		// Far above baseline, but too little of the scene.
+		assert!(!detector.is_spike(200, BLOCKS, 2000.0, 0.25, 3.0));
+	}
+
+	#[test]
+	fn lasting_change_becomes_the_new_normal() {
+		let mut detector = learned();
+
+		for _ in 0..MAX_SPIKE_FRAMES {
+			assert!(detector.is_spike(900, BLOCKS, 2000.0, 0.25, 3.0));
+		}
+		assert!(!detector.is_spike(900, BLOCKS, 2000.0, 0.25, 3.0));
+	}
+}
@@ -6,8 +6,31 @@ to test command line args:
 cargo run -- --version
 
 */
+mod autotune;
+mod camera;
+mod classify;
+mod clock;
+mod config;
+mod control;
+mod daynight;
 mod dbscan;
+mod denoise;
+mod direction;
+mod flow;
+mod heatmap;
+mod metrics;
+mod illumination;
 mod mvrprocessor;
+mod persistence;
+mod perspective;
+mod reader;
+mod recording;
+mod reload;
+mod render;
+mod shape;
+mod snapshot;
+mod tripwire;
+mod zones;
 
 use std::str::FromStr;
 use std::net::{TcpListener, SocketAddr};
@@ -15,14 +38,17 @@ use std::thread::spawn;
 use bufstream::BufStream;
 use std::sync::{Arc,RwLock,mpsc};
 use std::sync::mpsc::{Sender, Receiver};
+use clock::ClockSource;
+use control::Control;
+use serde::Serialize;
+use metrics::Metrics;
+use reload::Reloader;
 
 
 #[allow(unused_mut)]
 fn main()
 {
-/*
-	#[allow(unused_variables)]
-    let config = match parse_args() {
+    let mut config = match parse_args() {
         Ok(v) => v,
         Err(e) => {
             eprintln!("Error: {}.", e);
@@ -30,10 +56,38 @@ fn main()
         }
     };
 
-    println!("debug args: {:#?}", config);
-*/
-	let addr: SocketAddr = SocketAddr::from_str("0.0.0.0:8001").unwrap();
-    let listener = TcpListener::bind(addr).unwrap();
+    let base = config.clone();
+    if let Some(path) = config.config.clone() {
+        match config::load(&path, &mut config) {
+            Ok(file) => config.configfile = file,
+            Err(e) => {
+                eprintln!("Error: {}.", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Profiles are checked against the file when it is loaded; without one,
+    // there are none to switch to.
+    if config.config.is_none() && (config.dayprofile.is_some() || config.nightprofile.is_some()) {
+        eprintln!("Error: --dayprofile and --nightprofile name profiles in the --config file.");
+        std::process::exit(1);
+    }
+
+    let reloader = Reloader::new(config.clone());
+    if let Some(path) = config.config.clone() {
+        reloader.watch(path, base);
+    }
+
+    let metrics: Arc<Metrics> = Arc::new(Metrics::default());
+    if let Some(port) = &config.metricsport {
+        metrics::serve(format!("{}:{}", config.metricslisten, port), metrics.clone());
+    }
+
+    let control: Arc<Control> = Arc::new(Control::default());
+    if let Some(port) = &config.controlport {
+        control::serve(format!("{}:{}", config.controllisten, port), control.clone());
+    }
 
     let (send, recv): (Sender<String>, Receiver<String>) = mpsc::channel();
     let arc: Arc<RwLock<Vec<String>>> = Arc::new(RwLock::new(Vec::new()));
@@ -51,34 +105,132 @@ fn main()
         }
     });
 
-    for stream in listener.incoming() {
-        match stream {
-            Err(_) => println!("listen error"),
-            Ok(mut stream) => {
-                // println!("connection from {} to {}",
-                //          stream.peer_addr().unwrap(),
-                //          stream.local_addr().unwrap());
-                let send = send.clone();
-                let arc = arc.clone();
-                spawn(move|| {
-                    let mut stream = BufStream::new(stream);
-                    mvrprocessor::handle_raw_mvr_connection(&mut stream, send, arc);
-                });
+    // Play back a recording, as the camera it was made by.
+    if let Some(path) = config.input.clone() {
+        let (header, file) = match recording::open(&path) {
+            Ok(r) => r,
+            Err(e) => {
+                eprintln!("Error: {}.", e);
+                std::process::exit(1);
+            }
+        };
+
+        let mut config = match reloader.for_camera(&header.camera, None) {
+            Ok(c) => c,
+            Err(e) => {
+                eprintln!("Error: {}.", e);
+                std::process::exit(1);
             }
+        };
+        config.width = header.width;
+        config.height = header.height;
+        config.clock = ClockSource::Stream;
+        // Recorded after warm-up already, and played back much faster than
+        // real time, so a warm-up by the wall clock would skip it all.
+        config.warmupframes = 0;
+        config.warmupms = 0;
+
+        mvrprocessor::handle_raw_mvr_connection(file, send.clone(), arc, &config, metrics, header.camera, reloader, control);
+        std::process::exit(0);
+    }
+
+    // One listener per camera port, --port included.
+    let mut ports = vec![(config.port.clone(), None)];
+    for (port, id) in config.cameras.iter() {
+        match ports.iter_mut().find(|(p, _)| p == port) {
+            Some(p) => p.1 = id.clone(),
+            None => ports.push((port.clone(), id.clone())),
         }
     }
+
+    let listeners: Vec<_> = ports.into_iter().map(|(port, id)| {
+        let addr: SocketAddr = SocketAddr::from_str(&format!("{}:{}", config.listen, port)).unwrap();
+        let listener = TcpListener::bind(addr).unwrap();
+        let camera = id.unwrap_or(port);
+
+        let send = send.clone();
+        let arc = arc.clone();
+        let config = config.clone();
+        let metrics = metrics.clone();
+        let reloader = reloader.clone();
+        let control = control.clone();
+
+        spawn(move|| {
+            for stream in listener.incoming() {
+                match stream {
+                    Err(_) => println!("listen error"),
+                    Ok(mut stream) => {
+                        // println!("connection from {} to {}",
+                        //          stream.peer_addr().unwrap(),
+                        //          stream.local_addr().unwrap());
+                        let send = send.clone();
+                        let arc = arc.clone();
+                        let config = config.clone();
+                        let metrics = metrics.clone();
+                        let mut camera = camera.clone();
+                        let reloader = reloader.clone();
+                        let control = control.clone();
+                        spawn(move|| {
+                            let mut stream = BufStream::new(stream);
+                            if config.handshake {
+                                match camera::handshake(&mut stream) {
+                                    Ok(id) => camera = id,
+                                    Err(e) => {
+                                        eprintln!("Camera on port {}: {}", camera, e);
+                                        return;
+                                    }
+                                }
+                            }
+                            let config = match reloader.for_camera(&camera, None) {
+                                Ok(c) => c,
+                                Err(e) => {
+                                    eprintln!("Camera {}: {}", camera, e);
+                                    return;
+                                }
+                            };
+                            mvrprocessor::handle_raw_mvr_connection(stream, send, arc, &config, metrics, camera, reloader, control);
+                        });
+                    }
+                }
+            }
+        })
+    }).collect();
+
+    for listener in listeners {
+        let _ = listener.join();
+    }
 }
 
 
-#[allow(dead_code)]
 const HELP: &str = "\
 Xorzee MVR
 USAGE:
   mvr [OPTIONS]
 FLAGS:
   --help                This help information
+  --suppressnullframes  Do not cluster or update history for frames
+                        flagged as scene-wide illumination changes.
+  --pixelunits          Cluster size and shape limits are in sensor
+                        pixels rather than macroblocks.
+  --timing              Include time spent per processing stage in the
+                        frame info of every message.
+  --snapshotonalarm     Take a snapshot of the vector grid, candidates and
+                        clusters of every frame that raises an alarm.
+  --handshake           Inputs send their camera ID as a first line
+                        before any frame data.
+  --degrade             When frames have to be dropped because processing
+                        falls behind, do less work per frame for a while
+                        (no cluster points in output, fewer candidates).
 OPTIONS:
   --version             Outputs version of Xorzee MVR.
+  --config FILE         Read settings from a JSON file, with defaults for
+                        all cameras and overrides per camera ID. Settings
+                        are named like these options, without dashes.
+                        Settings in the file win over the command line.
+                        The file is reloaded when it changes or on
+                        SIGHUP; grid size, clock, ports and addresses
+                        are not changed by a reload.
+                        (default: none)
   --width NUMBER        Sets screen width in motion vectors.
                         (deafult: 121 for 1920)
   --height NUMBER       Sets screen height in motion vectors.
@@ -93,11 +245,22 @@ OPTIONS:
                         something as a cluster.
                         (default: 4)
   --listen ADDRESS      Sets IP address to listen to.
-                        (default: 127.0.0.1)
+                        (default: 0.0.0.0)
   --port PORT           Sets port to listen to.
                         (default: 8001)
-  --output [JSON|DEBUG] Set output to JSON or DEBUG.
+  --cameras PORTS       Also listen on these ports, one per camera, and
+                        optionally name the camera on each, e.g.
+                        8002=garden,8003=drive. A camera is known by
+                        its name, or else its port, in every message.
+                        (default: none)
+  --output [JSON|DEBUG] Set output to JSON or DEBUG. DEBUG also draws
+                        every frame: vectors coloured by direction and
+                        magnitude, candidates, tracks and their IDs; on
+                        the terminal (stderr) or into --debugdir.
                         (default: JSON)
+  --debugdir DIR        Write the frames drawn with --output DEBUG to DIR
+                        as PPM images instead.
+                        (default: none)
   --ignore POLYGONS     Set polygons to specify areas that should
                         be ignored.
                         (default: none)
@@ -107,10 +270,154 @@ OPTIONS:
   --sadthreshold NUMBER Set the minimum SAD that needs to be met to
                         classify a block as active.
                         (default: 250)
+  --autotune PERCENTILE Learn the SAD and magnitude thresholds from the
+                        noise in quiet frames, at this percentile of it
+                        (e.g. 99.5). --sadthreshold and --minmagnitude
+                        are used until enough has been learned.
+                        (default: none)
+  --dayprofile NAME     Set the profile from the --config file to use by
+                        day. (default: none, the camera's own settings)
+  --nightprofile NAME   Set the profile from the --config file to use at
+                        night. (default: none, the camera's own settings)
+  --nightschedule HH:MM-HH:MM
+                        Set when it is night, in local time (see
+                        --utcoffset).
+                        (default: none)
+  --nightnoise ENTER:LEAVE
+                        Without --nightschedule, it is night once the
+                        average SAD rises above ENTER, and day again when
+                        it drops below LEAVE.
+                        (default: none)
+  --utcoffset MINUTES   Set the offset of local time from UTC.
+                        (default: 0)
+  --nullframecoverage NUMBER
+                        Set the fraction of all blocks that must be
+                        active before a frame can be flagged as a
+                        scene-wide illumination change.
+                        (default: 0.25)
+  --nullframefactor NUMBER
+                        Set how many times above the rolling baseline
+                        the number of active blocks (or the mean SAD)
+                        must be to flag a frame.
+                        (default: 3)
+  --persistframes NUMBER
+                        Only let a block through as active if it (or a
+                        neighbour) was active in this many of the last
+                        --persistwindow frames. 1 disables the filter.
+                        (default: 1)
+  --persistwindow NUMBER
+                        Set the number of recent frames (max 32) looked
+                        at by --persistframes.
+                        (default: 4)
+  --medianfilter NUMBER Set the kernel size of the vector median filter
+                        run over all vectors. 0 disables it.
+                        (default: 0)
+  --maskopen NUMBER     Set the kernel size of the morphological open
+                        (removes specks) on active blocks. 0 disables it.
+                        (default: 0)
+  --maskclose NUMBER    Set the kernel size of the morphological close
+                        (fills holes) on active blocks. 0 disables it.
+                        (default: 0)
+  --warmupframes NUMBER Set the number of frames to skip at the start of
+                        each connection.
+                        (default: 0)
+  --warmupms NUMBER     Set the time (ms) to skip frames at the start of
+                        each connection. Both warm-up limits must be
+                        passed before frames are processed.
+                        (default: 1000)
+  --clock [MONOTONIC|FRAMERATE|STREAM]
+                        Set what time is measured on: elapsed time,
+                        frame number and --framerate, or a 64-bit
+                        little-endian timestamp (ms) sent before every
+                        frame.
+                        (default: MONOTONIC)
+  --framerate NUMBER    Set the frame rate used by --clock FRAMERATE.
+                        (default: 30)
+  --zones FILE          Set a JSON file with named zones and the rules
+                        that raise alarms in them.
+                        (default: none)
+  --lines FILE          Set a JSON file with tripwire lines to count
+                        tracks crossing in and out.
+                        (default: none)
+  --directions RANGES   Set the directions (degrees) clusters may move in
+                        to raise alarms, e.g. 315-45,90-135.
+                        (default: all)
+  --minclustermagnitude NUMBER
+                        Set the minimum average magnitude of a cluster
+                        for it to raise alarms.
+                        (default: 0)
+  --minarea NUMBER      Set the minimum/maximum area (active blocks) of
+  --maxarea NUMBER      a cluster.
+                        (default: none)
+  --minwidth NUMBER     Set the minimum/maximum width of the bounding
+  --maxwidth NUMBER     box of a cluster.
+                        (default: none)
+  --minheight NUMBER    Set the minimum/maximum height of the bounding
+  --maxheight NUMBER    box of a cluster.
+                        (default: none)
+  --minaspect NUMBER    Set the minimum/maximum aspect ratio (width /
+  --maxaspect NUMBER    height) of the bounding box of a cluster.
+                        (default: none)
+  --calibration REFS    Set reference objects for perspective as
+                        row:blocks:metres, e.g. 20:8:1.7,60:40:1.7 for a
+                        1.7 m person that is 8 blocks tall at row 20 and
+                        40 blocks at row 60. Size limits and epsilon are
+                        then valid for the middle row and are scaled.
+                        (default: none)
+  --classes FILE        Set a JSON file with thresholds for the
+                        classification of tracks.
+                        (default: built-in thresholds)
+  --heatmapinterval NUMBER
+                        Emit an activity heatmap every this many frames.
+                        0 disables the heatmap.
+                        (default: 0)
+  --heatmaphalflife NUMBER
+                        Set the time (ms) it takes for activity in the
+                        heatmap to fade to half. 0 never fades.
+                        (default: 3600000)
+  --heatmapfile FILE    Also write the heatmap to FILE when it is
+                        emitted or asked for on the control port; .csv
+                        for CSV, anything else for PGM.
+                        (default: none)
+  --flowinterval NUMBER Emit the average flow per tile every this many
+                        frames. 0 disables it.
+                        (default: 0)
+  --flowgrid COLSxROWS  Set the number of tiles for --flowinterval.
+                        (default: 8x8)
+  --metricsport PORT    Serve Prometheus-style metrics over HTTP on this
+                        port (on the --metricslisten address).
+                        (default: none)
+  --metricslisten ADDRESS
+                        Sets IP address to serve metrics on.
+                        (default: 127.0.0.1)
+  --snapshotdir DIR     Write snapshots to files in DIR rather than
+                        emitting them as messages.
+                        (default: none)
+  --recorddir DIR       When an alarm goes off, record raw frames to a
+                        file in DIR, from --prerollms before it until
+                        --postrollms after the last alarm.
+                        (default: none)
+  --prerollms NUMBER    Set how much (ms) to record before an alarm.
+                        (default: 5000)
+  --postrollms NUMBER   Set how much (ms) to record after an alarm.
+                        (default: 5000)
+  --input FILE          Process a recording instead of listening, and
+                        exit when done.
+                        (default: none)
+  --controlport PORT    Accept JSON requests, one per line, on this port
+                        (on the --controllisten address) to get and set
+                        settings, reset history, pause, resume, take
+                        snapshots and export heatmaps of connected
+                        cameras. There is no authentication.
+                        (default: none)
+  --controllisten ADDRESS
+                        Sets IP address to accept control requests on.
+                        (default: 127.0.0.1)
 ";
 
+// Serialized with the names of the settings (see config.rs).
 #[allow(dead_code)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct AppArgs {
     width: usize,
     height: usize,
@@ -122,19 +429,66 @@ pub struct AppArgs {
     output: String,
     ignore: String,
     discardafter: u32,
-    sadthreshold: u32
-
-    // number: u32,
-    // opt_number: u32,
-    // input: Option<std::path::PathBuf>,
-    // output: td::path::PathBuf,
+    sadthreshold: u32,
+    autotune: Option<f32>,
+    dayprofile: Option<String>,
+    nightprofile: Option<String>,
+    nightschedule: Option<(u32, u32)>,
+    nightnoise: Option<(f32, f32)>,
+    utcoffset: i32,
+    nullframecoverage: f32,
+    nullframefactor: f32,
+    suppressnullframes: bool,
+    persistframes: u32,
+    persistwindow: u32,
+    medianfilter: usize,
+    maskopen: usize,
+    maskclose: usize,
+    warmupframes: usize,
+    warmupms: u32,
+    clock: ClockSource,
+    framerate: f32,
+    zones: Vec<zones::Zone>,
+    lines: Vec<tripwire::Line>,
+    directions: Vec<(f32, f32)>,
+    minclustermagnitude: f32,
+    #[serde(flatten)]
+    shape: shape::ShapeFilter,
+    pixelunits: bool,
+    calibration: Vec<(f32, f32)>,
+    classes: classify::Thresholds,
+    heatmapinterval: u64,
+    heatmaphalflife: u32,
+    heatmapfile: Option<String>,
+    flowinterval: u64,
+    flowgrid: (usize, usize),
+    metricsport: Option<String>,
+    metricslisten: String,
+    timing: bool,
+    snapshotonalarm: bool,
+    snapshotdir: Option<String>,
+    debugdir: Option<String>,
+    recorddir: Option<String>,
+    prerollms: u32,
+    postrollms: u32,
+    input: Option<String>,
+    degrade: bool,
+    controlport: Option<String>,
+    controllisten: String,
+    #[serde(skip)]
+    cameras: Vec<(String, Option<String>)>,
+    handshake: bool,
+    #[serde(skip)]
+    config: Option<String>,
+    #[serde(skip)]
+    configfile: config::ConfigFile,
 }
 
-/*
-#[allow(dead_code)]
 fn parse_args() -> Result<AppArgs, pico_args::Error> {
+    parse_args_from(pico_args::Arguments::from_env())
+}
 
-    let mut pargs = pico_args::Arguments::from_env();
+fn parse_args_from(mut pargs: pico_args::Arguments) -> Result<AppArgs, pico_args::Error> {
 
     // Help has a higher priority and should be handled separately.
     if pargs.contains(["-h", "--help"]) {
@@ -142,33 +496,96 @@ fn parse_args() -> Result<AppArgs, pico_args::Error> {
         std::process::exit(0);
     }
 
+    if pargs.contains("--version") {
+        println!("Xorzee MVR {}", env!("CARGO_PKG_VERSION"));
+        std::process::exit(0);
+    }
+
     let args = AppArgs {
-        // Parses a required value that implements `FromStr`.
-        // Returns an error if not present.
-        // number: pargs.value_from_str("--number")?,
-
-        // Parses an optional value that implements `FromStr`.
-        // opt_number: pargs.opt_value_from_str("--opt-number")?,
-
-        // Parses an optional value from `&str` using a specified function.
-        width: pargs.opt_value_from_fn("--width", parse_number)?.unwrap_or(121),
-        height: pargs.opt_value_from_fn("--height", parse_number)?.unwrap_or(68),
-        minmagnitude: pargs.opt_value_from_fn("--minmagnitude", parse_number)?.unwrap_or(2),
-        epsilon: pargs.opt_value_from_fn("--epsilon", parse_number)?.unwrap_or(2),
-        minpoints: pargs.opt_value_from_fn("--minpoints", parse_number)?.unwrap_or(4),
-
-        listen: pargs.opt_value_from_fn("--listen", parse_ip)?.unwrap_or("127.0.0.1"),
-        port: pargs.opt_value_from_fn("--port", parse_port)?.unwrap_or("8001"),
-        output: pargs.opt_value_from_fn("--output", parse_output)?.unwrap_or("JSON"),
-        ignore: pargs.opt_value_from_fn("--ignore", parse_polygons)?.unwrap_or(""),
-        discardafter: pargs.opt_value_from_fn("--discardafter", parse_number)?.unwrap_or(2000),
-        sadthreshold: pargs.opt_value_from_fn("--sadthreshold", parse_number)?.unwrap_or(250),
-    
-        // Parses an optional value from `&OsStr` using a specified function.
-        // output: pargs.opt_value_from_os_str("--input", parse_path)?,
-
-        // Parses a required free-standing/positional argument.
-        // input: pargs.free_from_str()?,
+        width: pargs.opt_value_from_str("--width")?.unwrap_or(121),
+        height: pargs.opt_value_from_str("--height")?.unwrap_or(68),
+        minmagnitude: pargs.opt_value_from_str("--minmagnitude")?.unwrap_or(2.0),
+        epsilon: pargs.opt_value_from_str("--epsilon")?.unwrap_or(2.0),
+        minpoints: pargs.opt_value_from_str("--minpoints")?.unwrap_or(4),
+
+        listen: pargs.opt_value_from_str("--listen")?.unwrap_or_else(|| "0.0.0.0".to_string()),
+        port: pargs.opt_value_from_str("--port")?.unwrap_or_else(|| "8001".to_string()),
+        output: pargs.opt_value_from_str("--output")?.unwrap_or_else(|| "JSON".to_string()),
+        ignore: pargs.opt_value_from_str("--ignore")?.unwrap_or_default(),
+        discardafter: pargs.opt_value_from_str("--discardafter")?.unwrap_or(2000),
+        sadthreshold: pargs.opt_value_from_str("--sadthreshold")?.unwrap_or(250),
+        autotune: pargs.opt_value_from_str("--autotune")?,
+
+        dayprofile: pargs.opt_value_from_str("--dayprofile")?,
+        nightprofile: pargs.opt_value_from_str("--nightprofile")?,
+        nightschedule: pargs.opt_value_from_fn("--nightschedule", daynight::parse_schedule)?,
+        nightnoise: pargs.opt_value_from_fn("--nightnoise", daynight::parse_levels)?,
+        utcoffset: pargs.opt_value_from_str("--utcoffset")?.unwrap_or(0),
+
+        nullframecoverage: pargs.opt_value_from_str("--nullframecoverage")?.unwrap_or(0.25),
+        nullframefactor: pargs.opt_value_from_str("--nullframefactor")?.unwrap_or(3.0),
+        suppressnullframes: pargs.contains("--suppressnullframes"),
+
+        persistframes: pargs.opt_value_from_str("--persistframes")?.unwrap_or(1),
+        persistwindow: pargs.opt_value_from_str("--persistwindow")?.unwrap_or(4),
+
+        medianfilter: pargs.opt_value_from_str("--medianfilter")?.unwrap_or(0),
+        maskopen: pargs.opt_value_from_str("--maskopen")?.unwrap_or(0),
+        maskclose: pargs.opt_value_from_str("--maskclose")?.unwrap_or(0),
+
+        warmupframes: pargs.opt_value_from_str("--warmupframes")?.unwrap_or(0),
+        warmupms: pargs.opt_value_from_str("--warmupms")?.unwrap_or(1000),
+
+        clock: pargs.opt_value_from_str("--clock")?.unwrap_or(ClockSource::Monotonic),
+        framerate: pargs.opt_value_from_fn("--framerate", clock::parse_framerate)?.unwrap_or(30.0),
+
+        zones: pargs.opt_value_from_fn("--zones", zones::load_zones)?.unwrap_or_default(),
+        lines: pargs.opt_value_from_fn("--lines", tripwire::load_lines)?.unwrap_or_default(),
+
+        directions: pargs.opt_value_from_fn("--directions", direction::parse_ranges)?.unwrap_or_default(),
+        minclustermagnitude: pargs.opt_value_from_str("--minclustermagnitude")?.unwrap_or(0.0),
+
+        shape: shape::ShapeFilter {
+            min_area: pargs.opt_value_from_str("--minarea")?,
+            max_area: pargs.opt_value_from_str("--maxarea")?,
+            min_width: pargs.opt_value_from_str("--minwidth")?,
+            max_width: pargs.opt_value_from_str("--maxwidth")?,
+            min_height: pargs.opt_value_from_str("--minheight")?,
+            max_height: pargs.opt_value_from_str("--maxheight")?,
+            min_aspect: pargs.opt_value_from_str("--minaspect")?,
+            max_aspect: pargs.opt_value_from_str("--maxaspect")?,
+        },
+        pixelunits: pargs.contains("--pixelunits"),
+        calibration: pargs.opt_value_from_fn("--calibration", perspective::parse_references)?.unwrap_or_default(),
+        classes: pargs.opt_value_from_fn("--classes", classify::load_thresholds)?.unwrap_or_default(),
+
+        heatmapinterval: pargs.opt_value_from_str("--heatmapinterval")?.unwrap_or(0),
+        heatmaphalflife: pargs.opt_value_from_str("--heatmaphalflife")?.unwrap_or(3600000),
+        heatmapfile: pargs.opt_value_from_str("--heatmapfile")?,
+
+        flowinterval: pargs.opt_value_from_str("--flowinterval")?.unwrap_or(0),
+        flowgrid: pargs.opt_value_from_fn("--flowgrid", flow::parse_grid)?.unwrap_or((8, 8)),
+
+        metricsport: pargs.opt_value_from_str("--metricsport")?,
+        metricslisten: pargs.opt_value_from_str("--metricslisten")?.unwrap_or_else(|| "127.0.0.1".to_string()),
+        controlport: pargs.opt_value_from_str("--controlport")?,
+        controllisten: pargs.opt_value_from_str("--controllisten")?.unwrap_or_else(|| "127.0.0.1".to_string()),
+        timing: pargs.contains("--timing"),
+        snapshotonalarm: pargs.contains("--snapshotonalarm"),
+        snapshotdir: pargs.opt_value_from_str("--snapshotdir")?,
+        debugdir: pargs.opt_value_from_str("--debugdir")?,
+
+        recorddir: pargs.opt_value_from_str("--recorddir")?,
+        prerollms: pargs.opt_value_from_str("--prerollms")?.unwrap_or(5000),
+        postrollms: pargs.opt_value_from_str("--postrollms")?.unwrap_or(5000),
+        input: pargs.opt_value_from_str("--input")?,
+        degrade: pargs.contains("--degrade"),
+
+        cameras: pargs.opt_value_from_fn("--cameras", camera::parse_ports)?.unwrap_or_default(),
+        handshake: pargs.contains("--handshake"),
+
+        config: pargs.opt_value_from_str("--config")?,
+        configfile: config::ConfigFile::default(),
     };
 
     // It's up to the caller what to do with the remaining arguments.
@@ -179,4 +596,3 @@ fn parse_args() -> Result<AppArgs, pico_args::Error> {
 
     Ok(args)
 }
-*/
@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as FmtWrite;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread::spawn;
+use std::time::Duration;
+
+// Prometheus-style metrics, served over plain HTTP on its own port. Only on
+// 127.0.0.1 unless told otherwise (--metricslisten).
+//
+// Every input (camera) has its own set of counters; they are kept after
+// the input disconnects so that its last state can still be seen.
+
+pub const STAGES: [&str; 5] = ["parse", "reduce", "dbscan", "refine", "serialize"];
+
+// Upper bounds of latency buckets, in seconds.
+const BUCKETS: [f64; 10] = [0.0001, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25];
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Default, Clone)]
+struct Histogram {
+	buckets: [u64; BUCKETS.len()],
+	sum: f64,
+	count: u64,
+}
+
+impl Histogram {
+	fn observe(&mut self, seconds: f64) {
+		for (i, bound) in BUCKETS.iter().enumerate() {
+			if seconds <= *bound {
+				self.buckets[i] += 1;
+			}
+		}
+		self.sum += seconds;
+		self.count += 1;
+	}
+}
+
+#[derive(Default, Clone)]
+struct InputMetrics {
+	connected: bool,
+	frames_processed: u64,
+	frames_skipped: u64,
+	candidates: u64,
+	candidates_total: u64,
+	reduction_factor: u64,
+	active_tracks: u64,
+	dropped_messages: u64,
+	stages: [Histogram; STAGES.len()],
+}
+
+// name, type, help, value
+type Gauge = (&'static str, &'static str, &'static str, fn(&InputMetrics) -> u64);
+
+#[derive(Default)]
+pub struct Metrics {
+	inputs: Mutex<BTreeMap<String, InputMetrics>>,
+}
+
+// What a processed frame reports.
+pub struct FrameMetrics {
+	pub candidates: usize,
+	pub reduction_factor: usize,
+	pub active_tracks: usize,
+	pub stages: [Duration; STAGES.len()],
+}
+
+impl Metrics {
+	fn with_input<F: FnOnce(&mut InputMetrics)>(&self, input: &str, f: F) {
+		let mut inputs = self.inputs.lock().unwrap();
+		f(inputs.entry(input.to_string()).or_default());
+	}
+
+	pub fn connected(&self, input: &str, connected: bool) {
+		self.with_input(input, |m| m.connected = connected);
+	}
+
+	pub fn frame_skipped(&self, input: &str) {
+		self.with_input(input, |m| m.frames_skipped += 1);
+	}
+
+	pub fn message_dropped(&self, input: &str) {
+		self.with_input(input, |m| m.dropped_messages += 1);
+	}
+
+	pub fn frame_processed(&self, input: &str, frame: &FrameMetrics) {
+		self.with_input(input, |m| {
+			m.frames_processed += 1;
+			m.candidates = frame.candidates as u64;
+			m.candidates_total += frame.candidates as u64;
+			m.reduction_factor = frame.reduction_factor as u64;
+			m.active_tracks = frame.active_tracks as u64;
+
+			for i in 0..STAGES.len() {
+				m.stages[i].observe(frame.stages[i].as_secs_f64());
+			}
+		});
+	}
+
+	pub fn render(&self) -> String
+	{
+		let inputs = self.inputs.lock().unwrap();
+		let mut out = String::new();
+
+		let gauges: [Gauge; 8] = [
+			("mvr_input_connected", "gauge", "Whether the input is connected.", |m| m.connected as u64),
+			("mvr_frames_processed_total", "counter", "Frames processed.", |m| m.frames_processed),
+			("mvr_frames_skipped_total", "counter", "Frames skipped (warm-up, dropped).", |m| m.frames_skipped),
+			("mvr_candidates", "gauge", "Candidates in the last frame.", |m| m.candidates),
+			("mvr_candidates_total", "counter", "Candidates over all frames.", |m| m.candidates_total),
+			("mvr_reduction_factor", "gauge", "Reduction factor of the last frame.", |m| m.reduction_factor),
+			("mvr_active_tracks", "gauge", "Tracks in history.", |m| m.active_tracks),
+			("mvr_dropped_messages_total", "counter", "Output messages that could not be written.", |m| m.dropped_messages),
+		];
+
+		for (name, kind, help, value) in gauges.iter() {
+			let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+			for (input, m) in inputs.iter() {
+				let _ = writeln!(out, "{}{{input=\"{}\"}} {}", name, label(input), value(m));
+			}
+		}
+
+		let _ = writeln!(out, "# HELP mvr_stage_seconds Time spent per pipeline stage.\n# TYPE mvr_stage_seconds histogram");
+		for (input, m) in inputs.iter() {
+			for (s, stage) in STAGES.iter().enumerate() {
+				let h = &m.stages[s];
+				let labels = format!("input=\"{}\",stage=\"{}\"", label(input), stage);
+
+				for (b, bound) in BUCKETS.iter().enumerate() {
+					let _ = writeln!(out, "mvr_stage_seconds_bucket{{{},le=\"{}\"}} {}", labels, bound, h.buckets[b]);
+				}
+				let _ = writeln!(out, "mvr_stage_seconds_bucket{{{},le=\"+Inf\"}} {}", labels, h.count);
+				let _ = writeln!(out, "mvr_stage_seconds_sum{{{}}} {}", labels, h.sum);
+				let _ = writeln!(out, "mvr_stage_seconds_count{{{}}} {}", labels, h.count);
+			}
+		}
+
+		out
+	}
+}
+
+// 'value' escaped for use as a label value; camera IDs can come from the
+// camera itself (--handshake).
+fn label(value: &str) -> String
+{
+	value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+// Answers every HTTP request on 'addr' with the metrics.
+pub fn serve(addr: String, metrics: Arc<Metrics>)
+{
+	let listener = match TcpListener::bind(&addr) {
+		Ok(l) => l,
+		Err(e) => {
+			eprintln!("Could not listen for metrics on {}: {}", addr, e);
+			return;
+		}
+	};
+
+	spawn(move || {
+		for stream in listener.incoming().flatten() {
+			let metrics = metrics.clone();
+
+			// A thread per request, so that a client that does not send one
+			// holds up no one but itself (and only for so long).
+			spawn(move || {
+				let _ = stream.set_read_timeout(Some(REQUEST_TIMEOUT));
+				let _ = stream.set_write_timeout(Some(REQUEST_TIMEOUT));
+
+				// Read (and ignore) the request.
+				let mut reader = BufReader::new(&stream);
+				let mut line = String::new();
+				while reader.read_line(&mut line).map(|n| n > 2).unwrap_or(false) {
+					line.clear();
+				}
+
+				let body = metrics.render();
+				let mut stream = &stream;
+				let _ = write!(stream,
+					"HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+					body.len(), body
+				);
+			});
+		}
+	});
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn label_values_are_escaped() {
+		assert_eq!(label("garden"), "garden");
+		assert_eq!(label("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+	}
+}
@@ -1,13 +1,36 @@
-use std::net::{TcpStream};
-use bufstream::BufStream;
 use std::sync::{Arc,RwLock};
 use std::sync::mpsc::{Sender};
 use byteorder::{ByteOrder, LittleEndian};
 use std::f32::consts::PI;
 use std::io::prelude::*;
-use std::time::{Instant,SystemTime,UNIX_EPOCH};
+use std::time::{Duration, Instant};
 use std::collections::HashMap;
+use serde_json::{json, Value};
+use crate::autotune::AutoTuner;
+use crate::camera::Tagged;
+use crate::classify::{Class, Classifier};
+use crate::clock::FrameClock;
+use crate::config;
+use crate::daynight::DayNight;
+use crate::control::{Command, Control};
 use crate::dbscan::DbScan;
+use crate::denoise::Denoiser;
+use crate::direction::DirectionFilter;
+use crate::flow::FlowField;
+use crate::heatmap::Heatmap;
+use crate::metrics::{FrameMetrics, Metrics};
+use crate::illumination::IlluminationDetector;
+use crate::persistence::PersistenceFilter;
+use crate::perspective::Calibration;
+use crate::reader::spawn_reader;
+use crate::recording::{self, Recorder};
+use crate::render::Renderer;
+use crate::reload::Reloader;
+use crate::shape::ShapeFilter;
+use crate::snapshot;
+use crate::tripwire::{Crossing, LineCount, Tripwires};
+use crate::zones::{Alarm, ZoneEngine};
+use crate::AppArgs;
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone,Copy,Debug)]
@@ -43,16 +66,22 @@ impl MotionVector {
 #[derive(Clone,Debug)]
 #[derive(Serialize, Deserialize)]
 pub struct Cluster {
-	id: usize,
-	points: Vec<MotionVector>,
-	dir : f32,
-	mag : f32,
-	bbox : [i16; 4],
-	within: bool,
-	birth : u128,
-	age: u128,
-	active: u128,
-	size: usize,
+	pub id: usize,
+	pub points: Vec<MotionVector>,
+	pub dir : f32,
+	pub mag : f32,
+	pub bbox : [i16; 4],
+	pub within: bool,
+	#[serde(default)]
+	pub disallowed: bool,	// moving in a direction (or at a speed) we do not care about
+	pub birth : u128,
+	pub age: u128,
+	pub active: u128,
+	pub size: usize,		// in blocks, also those dropped by reduce_candidates
+	#[serde(skip_serializing_if = "Option::is_none", default)]
+	pub meters: Option<[f32; 2]>,	// estimated real-world width and height, if calibrated
+	#[serde(skip_serializing_if = "Option::is_none", default)]
+	pub class: Option<Class>,		// what we think it is (tracks in history only)
 }
 
 impl Cluster {
@@ -65,21 +94,54 @@ impl Cluster {
 			mag: 0.0,
 			bbox: [1000, 0, 0, 1000],
 			within: false,
+			disallowed: false,
 			birth: 0,
 			age: 0,
 			active: 0,
 			size: 0,
+			meters: None,
+			class: None,
 		}
     }
+
+	// Centre of the bounding box (which is [top, right, bottom, left]).
+	pub fn centre(&self) -> (f32, f32) {
+		(
+			(self.bbox[1] + self.bbox[3]) as f32 / 2.0,
+			(self.bbox[0] + self.bbox[2]) as f32 / 2.0,
+		)
+	}
 }
 
 #[allow(non_snake_case)]
 #[derive(Serialize, Deserialize)]
 struct FrameInfo {
-	nullFrame : bool,		// false whether we for some reason skipped processing this frame
+	nullFrame : bool,		// false, whether this frame looked like a scene-wide illumination change
 	totalMagnitude : i32,	// 0,	 total magnitude of all vectors in this frame
 	candidates : i32,		// 0,	 number of vectors/blocks that were deemed active in this frame
 	ignoredVectors : i32,	// 0,	 number of vectors that we found in an ignored area
+	rejectedClusters : i32,	// 0,	 number of clusters dropped for their size or shape
+	frame : u64,			// 0,	 sequence number of this frame on this connection
+	droppedFrames : u64,	// 0,	 frames dropped since the previous message as we were behind
+	degraded : u8,			// 0,	 load shedding level, see LoadShedding
+	reductionFactor : usize,// 1,	 how much the candidates were thinned out before clustering
+	sadThreshold : u32,		// 250,	 SAD threshold in effect (see --autotune)
+	magnitudeThreshold : f32,// 2,	 magnitude threshold in effect (see --autotune)
+	#[serde(skip_serializing_if = "Option::is_none", default)]
+	profile : Option<String>,	// profile in effect (see --dayprofile, --nightprofile)
+	#[serde(skip_serializing_if = "Option::is_none", default)]
+	timing : Option<FrameTiming>,	// only with --timing
+}
+
+// Time spent per stage, in microseconds.
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize)]
+struct FrameTiming {
+	parseUs : u64,
+	reduceUs : u64,
+	clusterUs : u64,
+	trackUs : u64,
+	serializeUs : u64,		// of the _previous_ frame; this one is not done yet
 }
 
 #[allow(non_snake_case)]
@@ -88,66 +150,282 @@ struct ClusterMessage {
 	clusters: Vec<Cluster>,
 	history: Vec<Cluster>,
 	frameInfo: FrameInfo,
+	#[serde(skip_serializing_if = "Vec::is_empty", default)]
+	alarms: Vec<Alarm>,
+	#[serde(skip_serializing_if = "Vec::is_empty", default)]
+	crossings: Vec<Crossing>,
+	#[serde(skip_serializing_if = "Vec::is_empty", default)]
+	lines: Vec<LineCount>,
 }
 
+// Messages about the state of the processor rather than about what it saw.
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "status")]
+enum StatusMessage {
+	#[serde(rename = "warmup")]
+	Warmup {
+		skippedFrames: usize,	// number of frames skipped at start of connection
+		elapsedMs: u128,		// time spent skipping them
+	},
+	#[serde(rename = "reloaded")]
+	Reloaded {
+		generation: u64,		// number of times the configuration was reloaded
+	},
+	#[serde(rename = "changed")]
+	Changed {
+		key: String,			// setting changed over the control socket
+		value: Value,
+	},
+	#[serde(rename = "reset")]
+	Reset,						// history cleared over the control socket
+	#[serde(rename = "profile")]
+	Profile {
+		profile: Option<String>,	// switched to, none for the camera's own settings
+		night: bool,
+	},
+	#[serde(rename = "recording")]
+	Recording {
+		file: String,			// raw frames around an alarm are written to this file
+	},
+	#[serde(rename = "recorded")]
+	Recorded {
+		file: String,
+		frames: usize,
+	},
+	#[serde(rename = "paused")]
+	Paused,
+	#[serde(rename = "resumed")]
+	Resumed,
+}
 
-#[allow(unused_variables)]
-pub fn handle_raw_mvr_connection(stream: &mut BufStream<TcpStream>, chan: Sender<String>, arc: Arc<RwLock<Vec<String>>>)
-{
-	const VECTORS_WIDTH: usize = 121;	// 1920
-	const VECTORS_HEIGHT: usize = 68;	// 1080
-	const VECTOR_COUNT: usize = VECTORS_WIDTH * VECTORS_HEIGHT;
-	const BUFSIZE: usize = VECTOR_COUNT * 4;
-	const DISCARD_CLUSTERS_AFTER: u128 = 2000;
-
-	let now = Instant::now();
 
-	let mut buffer = [0; BUFSIZE];
-	let mut vectors:Vec<MotionVector> = vec![MotionVector::new(); VECTOR_COUNT];
+#[allow(unused_variables, unused_assignments, clippy::too_many_arguments)]
+pub fn handle_raw_mvr_connection<R: Read + Send + 'static>(stream: R, chan: Sender<String>, arc: Arc<RwLock<Vec<String>>>, args: &AppArgs, metrics: Arc<Metrics>, camera: String, reloader: Arc<Reloader>, control: Arc<Control>)
+{
+	let mut args = args.clone();
+	let mut generation = reloader.generation();
+	let mut pending: Option<AppArgs> = None;
+	let mut paused = false;
+	let registration = control.register(&camera);
+	let vectors_width: usize = args.width;		// 121 for 1920
+	let vectors_height: usize = args.height;	// 68 for 1080
+	let vector_count: usize = vectors_width * vectors_height;
+	let bufsize: usize = vector_count * 4;
+
+	let mut warmup_started: Option<Instant> = None;
+	let mut warmup_skipped: usize = 0;
+	let mut warming_up = args.warmupframes > 0 || args.warmupms > 0;
+
+	let mut vectors:Vec<MotionVector> = vec![MotionVector::new(); vector_count];
 	let mut candidates:Vec<MotionVector> = vec![];
 	let mut frame_counter = 0;
 	let mut total_mag: f32;
+	let mut total_sad: u64;
 	let mut history: Vec<Cluster> = vec![];
-	let mut epoch;
 	let mut frame_start;
+	let mut clock = FrameClock::new(args.clock, args.framerate);
 	let mut last_history_id = 0;
+	let mut illumination = IlluminationDetector::new();
+	let mut persistence = PersistenceFilter::new(vectors_width, vectors_height, args.persistframes, args.persistwindow);
+	let mut denoiser = Denoiser::new(vectors_width, vectors_height, args.medianfilter, args.maskopen, args.maskclose);
+	let mut active: Vec<bool> = vec![false; vector_count];
+	let mut zones = ZoneEngine::new(args.zones.clone());
+	let mut tripwires = Tripwires::new(args.lines.clone());
+	let mut directions = DirectionFilter::new(args.directions.clone(), args.minclustermagnitude, &args.zones);
+	let mut shape = args.shape.in_blocks(args.pixelunits);
+	let mut calibration = Calibration::new(args.calibration.clone(), vectors_height as f32 / 2.0);
+	let mut row_epsilon: Vec<f32> = vec![];
+	let mut classifier = Classifier::new(args.classes.clone(), vector_count);
+	let mut heatmap = Heatmap::new(vectors_width, vectors_height, args.heatmaphalflife);
+	let mut flow = FlowField::new(vectors_width, vectors_height, args.flowgrid.0, args.flowgrid.1);
+	let mut renderer = Renderer::new(vectors_width, vectors_height, args.debugdir.clone());
+
+	let mut stages = [Duration::ZERO; 5];
+	let mut stage_start;
+	let mut load = LoadShedding::new();
+	let mut autotuner = AutoTuner::new(args.sadthreshold, args.minmagnitude);
+	let mut daynight = DayNight::new();
+	let mut profile: Option<String> = None;
+	let mut snapshot_replies = vec![];
+	let mut mask: Vec<bool> = vec![false; vector_count];
+	let mut recorder = Recorder::new();
 
 	// Do some prep-work.
-	for mv in (0..BUFSIZE).step_by(4) {
+	for mv in (0..bufsize).step_by(4) {
 		let index = mv / 4;
-		vectors[index].x = (index % VECTORS_WIDTH) as i16;
-		vectors[index].y = (index / VECTORS_WIDTH) as i16;
+		vectors[index].x = (index % vectors_width) as i16;
+		vectors[index].y = (index / vectors_width) as i16;
 	}
 
-	loop {
-        stream.read_exact(&mut buffer).unwrap(); //TODO: non-blocking read
-		
-		if (now.elapsed().as_millis()) < 1000 {
-			println!(r#"{{"err":"Just started; skipping frame"}}"#);
+	metrics.connected(&camera, true);
+
+	let frames = spawn_reader(stream, bufsize, clock.wants_timestamps(), args.input.is_some());
+
+	while let Some((frame, dropped)) = frames.take() {
+		let buffer = &frame.data;
+
+		if reloader.generation() != generation {
+			generation = reloader.generation();
+
+			match reloader.for_camera(&camera, profile.as_deref()) {
+				Ok(new) => {
+					pending = Some(new);
+					emit(&StatusMessage::Reloaded { generation }, &metrics, &camera);
+				},
+				Err(e) => eprintln!("Camera {}: not reloading configuration: {}.", camera, e),
+			}
+		}
+
+		while let Ok(request) = registration.requests.try_recv() {
+			let reply = match request.command {
+				Command::Get { key } => config::get(pending.as_ref().unwrap_or(&args), key.as_deref()),
+				Command::Set { key, value } => {
+					config::set(pending.as_ref().unwrap_or(&args), &key, &value).map(|new| {
+						pending = Some(new);
+						emit(&StatusMessage::Changed { key, value: value.clone() }, &metrics, &camera);
+						value
+					})
+				},
+				Command::Reset => {
+					history.clear();
+					emit(&StatusMessage::Reset, &metrics, &camera);
+					Ok(Value::Null)
+				},
+				Command::Pause => {
+					paused = true;
+					emit(&StatusMessage::Paused, &metrics, &camera);
+					Ok(Value::Null)
+				},
+				Command::Resume => {
+					paused = false;
+					emit(&StatusMessage::Resumed, &metrics, &camera);
+					Ok(Value::Null)
+				},
+				// Answered once this frame is processed.
+				Command::Snapshot => {
+					snapshot_replies.push(request.reply);
+					continue;
+				},
+				Command::Heatmap => export_heatmap(&heatmap, &args),
+				Command::Cameras => Err("not a camera command".to_string()),
+			};
+
+			let _ = request.reply.send(reply);
+		}
+
+		// Settings changed? Apply them between frames, keeping tracks (and
+		// zone and line state if those did not change).
+		if let Some(new) = pending.take() {
+			// Fixed for the lifetime of the connection.
+			let new = AppArgs {
+				width: args.width,
+				height: args.height,
+				clock: args.clock,
+				framerate: args.framerate,
+				..new
+			};
+
+			persistence.configure(new.persistframes, new.persistwindow);
+			denoiser = Denoiser::new(vectors_width, vectors_height, new.medianfilter, new.maskopen, new.maskclose);
+			if new.zones != args.zones {
+				zones = ZoneEngine::new(new.zones.clone());
+			}
+			if new.lines != args.lines {
+				tripwires = Tripwires::new(new.lines.clone());
+			}
+			directions = DirectionFilter::new(new.directions.clone(), new.minclustermagnitude, &new.zones);
+			shape = new.shape.in_blocks(new.pixelunits);
+			calibration = Calibration::new(new.calibration.clone(), vectors_height as f32 / 2.0);
+			classifier.set_thresholds(new.classes.clone());
+			heatmap.set_half_life(new.heatmaphalflife);
+			autotuner.set_fallback(new.sadthreshold, new.minmagnitude);
+			if new.degrade != args.degrade {
+				load = LoadShedding::new();
+			}
+			if new.flowgrid != args.flowgrid {
+				flow = FlowField::new(vectors_width, vectors_height, new.flowgrid.0, new.flowgrid.1);
+			}
+			if new.debugdir != args.debugdir {
+				renderer = Renderer::new(vectors_width, vectors_height, new.debugdir.clone());
+			}
+
+			args = new;
+		}
+
+		for _ in 0..dropped {
+			metrics.frame_skipped(&camera);
+		}
+
+		if paused {
+			for reply in snapshot_replies.drain(..) {
+				let _ = reply.send(Err("camera is paused".to_string()));
+			}
+			metrics.frame_skipped(&camera);
 			continue;
 		}
 
-		epoch = SystemTime::now()
-        	.duration_since(UNIX_EPOCH)
-        	.expect("Time was weird");
-		frame_start = epoch.as_millis();
+		// The first frames of a connection are often garbage (encoder still
+		// settling); skip them.
+		if warming_up {
+			let started = warmup_started.get_or_insert_with(Instant::now);
+
+			if warmup_skipped < args.warmupframes || started.elapsed().as_millis() < args.warmupms as u128 {
+				warmup_skipped += 1;
+				metrics.frame_skipped(&camera);
+				continue;
+			}
+
+			warming_up = false;
+
+			let status = StatusMessage::Warmup {
+				skippedFrames: warmup_skipped,
+				elapsedMs: started.elapsed().as_millis(),
+			};
+			emit(&status, &metrics, &camera);
+		}
+
+		stage_start = Instant::now();
+
+		frame_start = clock.tick(frame.sequence, frame.timestamp);
+
+		if args.recorddir.is_some() {
+			record(recorder.add(frame_start, buffer, args.prerollms as u128), &metrics, &camera);
+		}
+
+		// Fell behind? Do less.
+		if args.degrade {
+			load.update(dropped);
+		}
 
 		total_mag = 0.0;
+		total_sad = 0;
 		frame_counter += 1;
 
 		candidates.clear();
 
-		for mv in (0..BUFSIZE).step_by(4) {
+		for mv in (0..bufsize).step_by(4) {
 			let index = mv / 4;
 
-			vectors[index].dx = buffer[mv + 0] as i8;
+			vectors[index].dx = buffer[mv] as i8;
 			vectors[index].dy = buffer[mv + 1] as i8;
 
 			// TODO: what is it, actually: 1) signed/unsigned? 2) little/big endian?
 			// Note: Later I concluded that it is little endian and unsigned. 
 			// Why I did not delete this TODO, I do not know.
 			vectors[index].sad = LittleEndian::read_u16(&buffer[mv + 2..mv + 4]);
+			total_sad += vectors[index].sad as u64;
+		}
+
+		// Get rid of outliers and holes in the vector field.
+		denoiser.filter_vectors(&mut vectors);
+
+		let (sad_threshold, mag_threshold) = match args.autotune {
+			Some(_) => autotuner.thresholds(),
+			None => (args.sadthreshold, args.minmagnitude),
+		};
 
+		for index in 0..vector_count {
 			vectors[index].dir = (vectors[index].dy as f32).atan2(-(vectors[index].dx) as f32) * 180.0 / PI + 180.0;
 			vectors[index].mag = (
 				(
@@ -156,65 +434,294 @@ pub fn handle_raw_mvr_connection(stream: &mut BufStream<TcpStream>, chan: Sender
 				) as f32).sqrt();
 
 			// This SAD check is good for low-light conditions.
-			if vectors[index].mag >= 2.0 && vectors[index].sad > 250 {
+			active[index] = vectors[index].mag >= mag_threshold && vectors[index].sad as u32 > sad_threshold;
+		}
+
+		// Specks out, holes in.
+		if denoiser.filters_mask() {
+			denoiser.filter_mask(&mut active);
+		}
+
+		for index in 0..vector_count {
+			persistence.record(index, active[index]);
+
+			if active[index] {
 				// XXX: to include mag of all or just ones that are deemed active?
 				total_mag += vectors[index].mag;
-				candidates.push(vectors[index].clone());
+				candidates.push(vectors[index]);
 			}
 		}
 
-		// Reduce!
-		let (reduced, factor) = match reduce_candidates(&mut candidates) {
-			None => (false, 1),
-			Some((new_candidates, factor)) => {
-				candidates = new_candidates;
-				(true, factor)
-			}
-		};
+		// Lights switched on, a cloud passed, ... everything 'moved'.
+		let null_frame = illumination.is_spike(
+			candidates.len(), vector_count, total_sad as f32 / vector_count as f32,
+			args.nullframecoverage, args.nullframefactor
+		);
 
-		let mut results: Vec<usize> = vec![0x000000000000ffff_usize; candidates.len()];
-		let frame = &mut DbScan {
-			epsilon: 2.0,
-			min_points: 4,
-			data: &candidates,
-			results: &mut results,
+		// Switch profiles at dusk and dawn; applied from the next frame on.
+		let switch = match null_frame {
+			true => None,
+			false => daynight.update(args.nightschedule, args.nightnoise, args.utcoffset, frame_start, total_sad as f32 / vector_count as f32),
 		};
-		frame.run();
+		if let Some(night) = switch {
+			let name = if night { args.nightprofile.clone() } else { args.dayprofile.clone() };
+
+			match reloader.for_camera(&camera, name.as_deref()) {
+				Ok(new) => {
+					daynight.switched(night, frame_start);
+					profile = name;
+					pending = Some(new);
+					emit(&StatusMessage::Profile { profile: profile.clone(), night }, &metrics, &camera);
+				},
+				Err(e) => {
+					daynight.failed(frame_start);
+					eprintln!("Camera {}: not switching profile, trying again later: {}.", camera, e);
+				},
+			}
+		}
+
+		if let Some(percentile) = args.autotune {
+			autotuner.observe(&vectors, candidates.len(), !null_frame && history.is_empty(), percentile);
+		}
+
+		// Drop blocks that are only active in isolated frames.
+		if persistence.enabled() {
+			candidates.retain(|c| {
+				let keep = persistence.is_persistent(c.x, c.y);
+				if !keep {
+					total_mag -= c.mag;
+				}
+				keep
+			});
+		}
 
-		// debug_associate_result_candidates(&results, &candidates);
+		if args.snapshotonalarm || !snapshot_replies.is_empty() || args.output == "DEBUG" {
+			mask.iter_mut().for_each(|m| *m = false);
+			for c in candidates.iter() {
+				mask[c.y as usize * vectors_width + c.x as usize] = true;
+			}
+		}
 
-		let clusters = refine_clusters(&mut candidates, &results, reduced, &mut history, &frame_start, &mut last_history_id);
+		stages[0] = stage_start.elapsed();
+
+		if args.heatmapinterval > 0 && !null_frame {
+			heatmap.add(&candidates, frame_start);
+		}
 
-		if history.len() > 0 {
-			temporal_expiration(&mut history, &frame_start, DISCARD_CLUSTERS_AFTER);
+		if args.flowinterval > 0 && !null_frame {
+			flow.add(&vectors, &active);
+		}
+
+		let mut clusters = vec![];
+		let mut alarms = vec![];
+		let mut crossings = vec![];
+		let mut rejected = 0;
+		let mut reduction_factor = 1;
+		stages[1..4].iter_mut().for_each(|s| *s = Duration::ZERO);
+
+		if !(null_frame && args.suppressnullframes) {
+			// Reduce!
+			stage_start = Instant::now();
+			let (reduced, factor) = match reduce_candidates(&mut candidates, load.target_candidates()) {
+				None => (false, 1),
+				Some((new_candidates, factor)) => {
+					candidates = new_candidates;
+					(true, factor)
+				}
+			};
+			reduction_factor = factor;
+			stages[1] = stage_start.elapsed();
+
+			// Far away things are small; so are the gaps between their blocks.
+			// Never go below what it takes to link neighbouring blocks though.
+			if calibration.enabled() {
+				row_epsilon = (0..vectors_height)
+					.map(|row| (args.epsilon * calibration.scale((row * factor) as f32)).max(args.epsilon.min(2.0)))
+					.collect();
+			} else {
+				row_epsilon.clear();
+			}
+
+			stage_start = Instant::now();
+			let mut results: Vec<usize> = vec![0x000000000000ffff_usize; candidates.len()];
+			let frame = &mut DbScan {
+				epsilon: args.epsilon,
+				row_epsilon: &row_epsilon,
+				min_points: args.minpoints,
+				data: &candidates,
+				results: &mut results,
+			};
+			frame.run();
+			stages[2] = stage_start.elapsed();
+
+			// debug_associate_result_candidates(&results, &candidates);
+
+			stage_start = Instant::now();
+
+			(clusters, rejected) = refine_clusters(&mut candidates, &results, reduced, factor, &shape, &calibration, &mut history, &frame_start, &mut last_history_id);
+
+			if !history.is_empty() {
+				temporal_expiration(&mut history, &frame_start, args.discardafter as u128);
+			}
+
+			classifier.classify(&mut history, frame_start);
+
+			for c in clusters.iter_mut() {
+				c.disallowed = !directions.allows(c);
+			}
+
+			for c in history.iter_mut().filter(|c| c.active == frame_start) {
+				c.disallowed = !directions.allows(c);
+			}
+
+			alarms = zones.evaluate(&history, frame_start);
+
+			if let (Some(dir), false) = (&args.recorddir, alarms.is_empty()) {
+				let header = recording::Header::new(&camera, vectors_width, vectors_height);
+				record(recorder.trigger(dir, &header, frame_start, args.postrollms as u128), &metrics, &camera);
+			}
+			crossings = tripwires.evaluate(&history, frame_start);
+			stages[3] = stage_start.elapsed();
+		}
+
+		stage_start = Instant::now();
+
+		// What the processor saw, on request or when an alarm went off.
+		let alarmed = args.snapshotonalarm && !alarms.is_empty();
+		if alarmed || !snapshot_replies.is_empty() {
+			let snapshot = snapshot::take(
+				if alarmed { "alarm" } else { "request" }, frame.sequence, frame_start,
+				vectors_width, vectors_height, &vectors, &mask, &clusters
+			);
+
+			// Through a string: to_value() can't do the u128 timestamps.
+			let reply = serde_json::to_string(&history)
+				.and_then(|s| serde_json::from_str::<Value>(&s))
+				.map(|history| json!({ "history": history, "snapshot": snapshot.snapshot }))
+				.map_err(|e| e.to_string());
+			for r in snapshot_replies.drain(..) {
+				let _ = r.send(reply.clone());
+			}
+
+			if alarmed {
+				match &args.snapshotdir {
+					Some(dir) => if let Err(e) = snapshot::write(dir, &camera, &snapshot) {
+						eprintln!("Could not write snapshot to {}: {}", dir, e);
+					},
+					None => emit(&snapshot, &metrics, &camera),
+				}
+			}
 		}
 
 		// TODO: Can I get rid of this .clone() somehow?
 		let msg = ClusterMessage {
-			clusters: clusters,
-			history: history.clone(),
+			clusters: load.strip(clusters),
+			history: load.strip(history.clone()),
 			frameInfo: FrameInfo {
 				totalMagnitude: total_mag as i32,
 				candidates: candidates.len() as i32,
-    			nullFrame: false,		// TODO see definition
+    			nullFrame: null_frame,
     			ignoredVectors: 0,		// TODO see definition
-			}
+				rejectedClusters: rejected as i32,
+				frame: frame.sequence,
+				droppedFrames: dropped,
+				degraded: load.level,
+				reductionFactor: reduction_factor,
+				sadThreshold: sad_threshold,
+				magnitudeThreshold: mag_threshold,
+				profile: profile.clone(),
+				timing: args.timing.then(|| FrameTiming {
+					parseUs: stages[0].as_micros() as u64,
+					reduceUs: stages[1].as_micros() as u64,
+					clusterUs: stages[2].as_micros() as u64,
+					trackUs: stages[3].as_micros() as u64,
+					serializeUs: stages[4].as_micros() as u64,
+				}),
+			},
+			alarms,
+			crossings,
+			lines: tripwires.counts().to_vec(),
 		};
 
-		let json = serde_json::to_string(&msg).unwrap();
+		emit(&msg, &metrics, &camera);
+		stages[4] = stage_start.elapsed();
+
+		metrics.frame_processed(&camera, &FrameMetrics {
+			candidates: msg.frameInfo.candidates as usize,
+			reduction_factor,
+			active_tracks: history.len(),
+			stages,
+		});
+
+		if args.output == "DEBUG" {
+			let tracks: Vec<&Cluster> = history.iter().filter(|c| c.active == frame_start).collect();
+
+			if let Err(e) = renderer.render(&camera, frame.sequence, &vectors, &mask, &tracks) {
+				eprintln!("Could not render frame {}: {}", frame.sequence, e);
+			}
+		}
+
+		if args.flowinterval > 0 && frame_counter % args.flowinterval == 0 {
+			emit(&flow.take(), &metrics, &camera);
+		}
+
+		if args.heatmapinterval > 0 && frame_counter % args.heatmapinterval == 0 {
+			emit(&heatmap.message(), &metrics, &camera);
 
-		println!("{}", json);
+			if let Err(e) = export_heatmap(&heatmap, &args) {
+				eprintln!("{}", e);
+			}
+		}
     }
+
+	record(recorder.finish(), &metrics, &camera);
+	metrics.connected(&camera, false);
+}
+
+
+// Announces recordings starting and finishing.
+fn record(result: std::io::Result<Option<recording::Event>>, metrics: &Metrics, camera: &str)
+{
+	match result {
+		Ok(Some(recording::Event::Started(file))) => emit(&StatusMessage::Recording { file }, metrics, camera),
+		Ok(Some(recording::Event::Finished(file, frames))) => emit(&StatusMessage::Recorded { file, frames }, metrics, camera),
+		Ok(None) => (),
+		Err(e) => eprintln!("Camera {}: could not record: {}", camera, e),
+	}
+}
+
+// Writes the heatmap to --heatmapfile, if given, and returns it.
+fn export_heatmap(heatmap: &Heatmap, args: &AppArgs) -> Result<Value, String>
+{
+	if args.heatmapinterval == 0 {
+		return Err("heatmap is disabled (see heatmapinterval)".to_string());
+	}
+
+	if let Some(path) = &args.heatmapfile {
+		heatmap.export(path).map_err(|e| format!("Could not export heatmap to {}: {}", path, e))?;
+	}
+
+	serde_json::to_value(heatmap.message().heatmap).map_err(|e| e.to_string())
+}
+
+
+// Writes a message as a line of JSON on stdout. If that fails, the message is
+// counted as dropped rather than taking the connection down.
+fn emit<T: Serialize>(msg: &T, metrics: &Metrics, camera: &str)
+{
+	let json = serde_json::to_string(&Tagged { camera, message: msg }).unwrap();
+	let mut out = std::io::stdout().lock();
+
+	if writeln!(out, "{}", json).and_then(|_| out.flush()).is_err() {
+		metrics.message_dropped(camera);
+	}
 }
 
 
 #[allow(dead_code)]
-fn debug_associate_result_candidates(results: &Vec<usize>, candidates: &Vec<MotionVector>)
+fn debug_associate_result_candidates(results: &[usize], candidates: &[MotionVector])
 {
-	let max_clusters = match results.iter().max() {
-		Some(max) => max,
-		None => &0_usize
-	};
+	let max_clusters = results.iter().max().unwrap_or(&0_usize);
 
 	// Clusters[ (x,y), (x,y), ... ]
 	let mut debug_vec: Vec<Vec<(usize,usize)>> = vec![vec![]; *max_clusters + 1];
@@ -224,7 +731,7 @@ fn debug_associate_result_candidates(results: &Vec<usize>, candidates: &Vec<Moti
 			continue;
 		}
 
-		if debug_vec[results[i]].len() > 0 {
+		if !debug_vec[results[i]].is_empty() {
 			debug_vec[results[i]].push( 
 				(candidates[i].org_x as usize, candidates[i].org_y as usize)
 			);
@@ -235,19 +742,19 @@ fn debug_associate_result_candidates(results: &Vec<usize>, candidates: &Vec<Moti
 		}
 	}
 
-	for i in 0..debug_vec.len() {
+	for (i, points) in debug_vec.iter().enumerate() {
 		let mut minx = 1000;
 		let mut maxx = 0;
 		let mut miny = 1000;
 		let mut maxy = 0;
 
-		if debug_vec[i].len() > 0 {
-			for j in 0..debug_vec[i].len() {
-				if debug_vec[i][j].0 < minx { minx = debug_vec[i][j].0; }
-				if debug_vec[i][j].0 > maxx { maxx = debug_vec[i][j].0; }
+		if !points.is_empty() {
+			for p in points {
+				if p.0 < minx { minx = p.0; }
+				if p.0 > maxx { maxx = p.0; }
 
-				if debug_vec[i][j].1 < miny { miny = debug_vec[i][j].1; }
-				if debug_vec[i][j].1 > maxy { maxy = debug_vec[i][j].1; }
+				if p.1 < miny { miny = p.1; }
+				if p.1 > maxy { maxy = p.1; }
 			}
 		} else {
 			minx = 0;
@@ -260,31 +767,75 @@ fn debug_associate_result_candidates(results: &Vec<usize>, candidates: &Vec<Moti
 			i, 
 			maxx - minx,
 			maxy - miny,
-			debug_vec[i].len(), 
-			debug_vec[i]
+			points.len(), 
+			points
 		);
 	}
 }
 
 
+// When we fall behind (frames get dropped), do less work per frame:
+//	level 1: do not send the points of clusters
+//	level 2: also cluster on fewer candidates
+// A level is dropped again after a while without any frames being dropped.
+struct LoadShedding {
+	level: u8,
+	calm_frames: usize,
+}
+
+const LOAD_CALM_FRAMES: usize = 90;
+
+impl LoadShedding {
+	fn new() -> LoadShedding {
+		LoadShedding {
+			level: 0,
+			calm_frames: 0,
+		}
+	}
+
+	fn update(&mut self, dropped: u64) {
+		if dropped > 0 {
+			self.level = (self.level + 1).min(2);
+			self.calm_frames = 0;
+		} else if self.level > 0 {
+			self.calm_frames += 1;
+			if self.calm_frames >= LOAD_CALM_FRAMES {
+				self.level -= 1;
+				self.calm_frames = 0;
+			}
+		}
+	}
+
+	fn target_candidates(&self) -> usize {
+		if self.level >= 2 { 50 } else { 100 }
+	}
+
+	fn strip(&self, mut clusters: Vec<Cluster>) -> Vec<Cluster> {
+		if self.level >= 1 {
+			clusters.iter_mut().for_each(|c| c.points.clear());
+		}
+		clusters
+	}
+}
+
+
 // The idea: If we have a lot of candidates: Shrink the dataset by reducing 'resolution'
 // remove every Nth and divide the coordinate of vector by N
 // let's say, if it is above 200 (nee 400) points, get it down to that...
-fn reduce_candidates(candidates: &mut Vec<MotionVector>) -> Option<(Vec<MotionVector>, usize)>
+fn reduce_candidates(candidates: &mut [MotionVector], target_candidates: usize) -> Option<(Vec<MotionVector>, usize)>
 {
 	let reduction_factor;
-	let target_candidates = 100;
 
 	// was * 1.25, but I am less picky about filtering out in pre-stage now...
 	if candidates.len() as f32 > (target_candidates as f32 * 1.25) {
-		reduction_factor = (candidates.len() / target_candidates) as usize;
+		reduction_factor = candidates.len() / target_candidates;
 		let mut reduced_candidates: Vec<MotionVector> = vec![];
 
 		for i in (0..candidates.len()).step_by(reduction_factor) {
 			candidates[i].org_x = candidates[i].x;
 			candidates[i].org_y = candidates[i].y;
-			candidates[i].x = candidates[i].x / reduction_factor as i16;
-			candidates[i].y = candidates[i].y / reduction_factor as i16;
+			candidates[i].x /= reduction_factor as i16;
+			candidates[i].y /= reduction_factor as i16;
 
 			reduced_candidates.push(candidates[i]);
 		}
@@ -307,12 +858,14 @@ fn reduce_candidates(candidates: &mut Vec<MotionVector>) -> Option<(Vec<MotionVe
  *	we then take candidates[i] and throw that into a grouped
  *	collection. Ie. cluster[cluster-id] = [ candidates... ]
  */
+#[allow(clippy::too_many_arguments)]
 fn refine_clusters(
-	candidates: &mut Vec<MotionVector>, results: &Vec<usize>, reduced: bool, 
-	history: &mut Vec<Cluster>, now: &u128, last_history_id: &mut usize) -> Vec<Cluster>
+	candidates: &mut [MotionVector], results: &[usize], reduced: bool, reduction_factor: usize,
+	shape: &ShapeFilter, calibration: &Calibration, history: &mut Vec<Cluster>, now: &u128, last_history_id: &mut usize) -> (Vec<Cluster>, usize)
 {
 	let mut cluster: &mut Cluster;
 	let mut clusters_map: HashMap<usize, Cluster> = HashMap::new();
+	let mut headings: HashMap<usize, (f32, f32)> = HashMap::new();
 
 	// A result refers to an index in candidates
 	for i in 0..results.len() {
@@ -329,9 +882,12 @@ fn refine_clusters(
 			mag: 0.0,
 			bbox: [1000,0,0,1000],
 			within: false,
+			disallowed: false,
 			age: 0,
 			active: 0,
 			size: 0,
+			meters: None,
+			class: None,
 		});
 
 		if reduced {
@@ -358,40 +914,59 @@ fn refine_clusters(
 			cluster.bbox[3] = candidates[i].x;
 		}
 
-		cluster.dir += candidates[i].dir;
 		cluster.mag += candidates[i].mag;
+
+		// Directions are averaged as unit vectors; right is both 0 and 360.
+		let heading = headings.entry(results[i]).or_insert((0.0, 0.0));
+		heading.0 += candidates[i].dir.to_radians().cos();
+		heading.1 += candidates[i].dir.to_radians().sin();
 	}
 
-	let mut clusters: Vec<Cluster> = clusters_map.into_values().collect();
+	let mut clusters: Vec<Cluster> = clusters_map.into_iter().map(|(ix, mut c)| {
+		let (x, y) = headings[&ix];
+		c.dir = y.atan2(x).to_degrees().rem_euclid(360.0);
+		c
+	}).collect();
+
+	// Too small, too big, too thin...
+	let found = clusters.len();
+	clusters.retain(|c| shape.accepts(c, reduction_factor, calibration));
+	let rejected = found - clusters.len();
 
 	for k in 0..clusters.len() {
+		// Averages before they go into history.
+		clusters[k].mag /= clusters[k].points.len() as f32;
+		clusters[k].size = clusters[k].points.len() * reduction_factor;
+
+		let bbox = clusters[k].bbox;
+		clusters[k].meters = calibration.to_meters(
+			(bbox[1] - bbox[3] + 1) as f32, (bbox[2] - bbox[0] + 1) as f32, bbox[2] as f32
+		);
+
 		if is_within(k, &clusters) {
 			clusters[k].within = true;
 		} else {
 			// Cluster is not discarded
 			track_temporal(history, &mut clusters[k], now, last_history_id);
 		}
-
-		clusters[k].dir /= clusters[k].points.len() as f32;
-		clusters[k].mag /= clusters[k].points.len() as f32;
 	}
 
-	clusters
+	(clusters, rejected)
 }
 
-fn is_within(my_index: usize, others: &Vec<Cluster>) -> bool
+fn is_within(my_index: usize, others: &[Cluster]) -> bool
 {
 	let cluster = &others[my_index];
 
-	for k in 0..others.len() {
+	for (k, other) in others.iter().enumerate() {
 		if k == my_index {
 			continue;
 		}
 
-		if cluster.bbox[0] >= others[k].bbox[0] 			// >= top
-	  		&& cluster.bbox[2] <= others[k].bbox[2] 		// <= bottom
-	  		&& cluster.bbox[3] >= others[k].bbox[3]			// >= left
-	  		&& cluster.bbox[1] <= others[k].bbox[1] {		// <= right
+		if cluster.bbox[0] >= other.bbox[0] 			// >= top
+	  		&& cluster.bbox[2] <= other.bbox[2] 		// <= bottom
+	  		&& cluster.bbox[3] >= other.bbox[3]			// >= left
+	  		&& cluster.bbox[1] <= other.bbox[1] {		// <= right
 			return true;
 		}
 	}
@@ -408,16 +983,17 @@ fn track_temporal(history: &mut Vec<Cluster>, cluster: &mut Cluster, now: &u128,
 			// println!("UPDATING CLUSTER ID {}", overlapping.id);
 
 			// update cluster in history
-			cluster.age = now - overlapping.birth;
+			cluster.age = now.saturating_sub(overlapping.birth);
 			overlapping.active = *now;
 			overlapping.age = cluster.age;
 
-			overlapping.bbox = cluster.bbox.clone();
-			overlapping.size = cluster.points.len();
+			overlapping.bbox = cluster.bbox;
+			overlapping.size = cluster.size;
 
 			overlapping.points = cluster.points.clone();
 			overlapping.mag = cluster.mag;
 			overlapping.dir = cluster.dir;
+			overlapping.meters = cluster.meters;
 		},
 		None => {
 			// add new cluster to history
@@ -431,12 +1007,15 @@ fn track_temporal(history: &mut Vec<Cluster>, cluster: &mut Cluster, now: &u128,
 				active : *now,
 				birth : *now,
 				within: cluster.within,
-				bbox : cluster.bbox.clone(),
-				size : cluster.points.len(),
+				disallowed: cluster.disallowed,
+				bbox : cluster.bbox,
+				size : cluster.size,
 
 				points : cluster.points.clone(),
 				mag : cluster.mag,
-				dir : cluster.dir
+				dir : cluster.dir,
+				meters : cluster.meters,
+				class : cluster.class,
 			});
 		}
 	}
@@ -445,19 +1024,13 @@ fn track_temporal(history: &mut Vec<Cluster>, cluster: &mut Cluster, now: &u128,
 fn temporal_expiration(history: &mut Vec<Cluster>, now: &u128, expire_after: u128)
 {
 	history.retain(|v| {
-		(now - v.active) <= expire_after
+		now.saturating_sub(v.active) <= expire_after
 	});
 }
 
-fn overlaps_any(c: &Cluster, history: &Vec<Cluster>) -> Option<usize>
+fn overlaps_any(c: &Cluster, history: &[Cluster]) -> Option<usize>
 {
-	for i in 0..history.len() {
-		if overlaps(c, &history[i]) {
-			return Some(i);
-		}
-	}
-
-	None
+	history.iter().position(|h| overlaps(c, h))
 }
 
 fn overlaps(c1: &Cluster, c2: &Cluster) -> bool
@@ -0,0 +1,137 @@
+// Temporal persistence filter (N-of-M).
+//
+// A block that qualifies as a candidate in one isolated frame is usually just
+// encoder noise. For every block we keep a ring of activity bits, one bit per
+// frame, newest frame in the lowest bit. A candidate is only let through if
+// its block -- or one of its eight neighbours, things do move -- was active in
+// at least 'min_frames' of the last 'window' frames (this one included).
+
+pub struct PersistenceFilter {
+	width: usize,
+	height: usize,
+	min_frames: u32,
+	window_mask: u32,
+	activity: Vec<u32>,
+}
+
+impl PersistenceFilter {
+	pub fn new(width: usize, height: usize, min_frames: u32, window: u32) -> PersistenceFilter {
+		let mut filter = PersistenceFilter {
+			width,
+			height,
+			min_frames: 0,
+			window_mask: 0,
+			activity: vec![0; width * height],
+		};
+		filter.configure(min_frames, window);
+		filter
+	}
+
+	// Changes N and M. Activity seen so far is forgotten when the window
+	// changes size, as it was recorded for another window.
+	pub fn configure(&mut self, min_frames: u32, window: u32) {
+		let window = window.clamp(1, 32);
+		let window_mask = if window == 32 { u32::MAX } else { (1 << window) - 1 };
+
+		if window_mask != self.window_mask {
+			self.activity.iter_mut().for_each(|a| *a = 0);
+			self.window_mask = window_mask;
+		}
+		self.min_frames = min_frames.min(window);
+	}
+
+	pub fn enabled(&self) -> bool {
+		self.min_frames > 1
+	}
+
+	// Must be called for every block of a frame before asking is_persistent().
+	pub fn record(&mut self, index: usize, active: bool) {
+		self.activity[index] = (self.activity[index] << 1) | active as u32;
+	}
+
+	pub fn is_persistent(&self, x: i16, y: i16) -> bool {
+		let mut bits: u32 = 0;
+
+		let x_from = (x as usize).saturating_sub(1);
+		let x_to = (x as usize + 1).min(self.width - 1);
+		let y_from = (y as usize).saturating_sub(1);
+		let y_to = (y as usize + 1).min(self.height - 1);
+
+		for ny in y_from..=y_to {
+			for nx in x_from..=x_to {
+				bits |= self.activity[ny * self.width + nx];
+			}
+		}
+
+		(bits & self.window_mask).count_ones() >= self.min_frames
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// One frame, with only the blocks in 'active' active.
+	fn frame(filter: &mut PersistenceFilter, active: &[usize]) {
+		for i in 0..filter.activity.len() {
+			filter.record(i, active.contains(&i));
+		}
+	}
+
+	#[test]
+	fn needs_n_hits_within_m_frames() {
+		let mut filter = PersistenceFilter::new(5, 5, 2, 3);
+		let block = 2 * 5 + 2;
+
+		frame(&mut filter, &[block]);
+		assert!(!filter.is_persistent(2, 2));
+
+		frame(&mut filter, &[]);
+		frame(&mut filter, &[block]);
+		assert!(filter.is_persistent(2, 2));
+
+		// The first hit falls out of the window.
+		frame(&mut filter, &[]);
+		assert!(!filter.is_persistent(2, 2));
+	}
+
+	#[test]
+	fn neighbours_count_too() {
+		let mut filter = PersistenceFilter::new(5, 5, 2, 3);
+
+		frame(&mut filter, &[2 * 5 + 1]);
+		frame(&mut filter, &[2 * 5 + 2]);
+		assert!(filter.is_persistent(2, 2));
+		assert!(filter.is_persistent(1, 1));
+		assert!(!filter.is_persistent(4, 2));
+
+		// Corners have fewer neighbours.
+		frame(&mut filter, &[0]);
+		frame(&mut filter, &[1]);
+		assert!(filter.is_persistent(0, 0));
+	}
+
+	#[test]
+	fn disabled_for_one_frame() {
+		assert!(!PersistenceFilter::new(5, 5, 1, 3).enabled());
+		assert!(PersistenceFilter::new(5, 5, 2, 3).enabled());
+		// N can not be more than M.
+		assert!(!PersistenceFilter::new(5, 5, 4, 1).enabled());
+	}
+
+	#[test]
+	fn activity_is_forgotten_when_the_window_changes() {
+		let mut filter = PersistenceFilter::new(5, 5, 2, 3);
+
+		frame(&mut filter, &[12]);
+		frame(&mut filter, &[12]);
+		filter.configure(3, 3);
+		frame(&mut filter, &[12]);
+		assert!(filter.is_persistent(2, 2));
+
+		filter.configure(2, 4);
+		assert!(!filter.is_persistent(2, 2));
+		frame(&mut filter, &[12]);
+		assert!(!filter.is_persistent(2, 2));
+	}
+}
@@ -0,0 +1,150 @@
+// Perspective calibration from a ground plane.
+//
+// A person close to the camera covers hundreds of blocks, one at the far end
+// of the scene covers four. To be able to use the same thresholds everywhere,
+// the scale of the scene is calibrated by giving the size in blocks of
+// reference objects of known height (in metres) at two or more rows, e.g. a
+// 1.7 m person that is 8 blocks tall when standing at row 20 and 40 blocks tall
+// at row 60: "20:8:1.7,60:40:1.7". Scale at other rows is interpolated (or
+// extrapolated) linearly.
+//
+// Thresholds (cluster size limits, DbScan epsilon) are taken to be valid at the
+// middle row of the frame and are scaled from there. Objects stand on the
+// ground, so the scale of a cluster is that of the bottom row of its bounding
+// box.
+
+// Never let the scale collapse (extrapolating towards the horizon).
+const MIN_BLOCKS_PER_METER: f32 = 0.1;
+
+#[derive(Debug, Clone, Default)]
+pub struct Calibration {
+	refs: Vec<(f32, f32)>,		// (row, blocks per metre), sorted by row
+	reference_row: f32,
+}
+
+impl Calibration {
+	pub fn new(mut refs: Vec<(f32, f32)>, reference_row: f32) -> Calibration {
+		refs.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+		Calibration {
+			refs,
+			reference_row,
+		}
+	}
+
+	pub fn enabled(&self) -> bool {
+		!self.refs.is_empty()
+	}
+
+	pub fn blocks_per_meter(&self, row: f32) -> Option<f32>
+	{
+		let bpm = match self.refs.len() {
+			0 => return None,
+			1 => self.refs[0].1,
+			n => {
+				// Segment to interpolate on; outermost ones extrapolate.
+				let i = self.refs.iter().position(|r| r.0 > row).unwrap_or(n).clamp(1, n - 1);
+				let (r0, b0) = self.refs[i - 1];
+				let (r1, b1) = self.refs[i];
+
+				if r1 == r0 { b0 } else { b0 + (b1 - b0) * (row - r0) / (r1 - r0) }
+			}
+		};
+
+		Some(bpm.max(MIN_BLOCKS_PER_METER))
+	}
+
+	// How much bigger things are at 'row' than at the reference row.
+	pub fn scale(&self, row: f32) -> f32
+	{
+		match (self.blocks_per_meter(row), self.blocks_per_meter(self.reference_row)) {
+			(Some(at_row), Some(at_reference)) => at_row / at_reference,
+			_ => 1.0,
+		}
+	}
+
+	// Estimated real-world width and height (metres) of something standing at
+	// 'row'.
+	pub fn to_meters(&self, width: f32, height: f32, row: f32) -> Option<[f32; 2]>
+	{
+		self.blocks_per_meter(row).map(|bpm| [width / bpm, height / bpm])
+	}
+}
+
+// Parses "row:blocks:metres,row:blocks:metres,..." into (row, blocks per metre).
+pub fn parse_references(s: &str) -> Result<Vec<(f32, f32)>, String>
+{
+	s.split(',')
+		.map(|reference| {
+			let parts: Vec<f32> = reference
+				.split(':')
+				.map(|v| v.trim().parse::<f32>().map_err(|e| format!("'{}': {}", reference, e)))
+				.collect::<Result<_, _>>()?;
+
+			match parts[..] {
+				[row, blocks, meters] if blocks > 0.0 && meters > 0.0 => Ok((row, blocks / meters)),
+				_ => Err(format!("'{}' is not row:blocks:metres", reference)),
+			}
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn references_are_parsed() {
+		assert_eq!(parse_references("20:10:2, 60:40:2"), Ok(vec![(20.0, 5.0), (60.0, 20.0)]));
+		assert!(parse_references("20:10").is_err());
+		assert!(parse_references("20:10:0").is_err());
+		assert!(parse_references("20:0:2").is_err());
+		assert!(parse_references("20:-4:2").is_err());
+		assert!(parse_references("20:ten:2").is_err());
+	}
+
+	#[test]
+	fn scale_is_interpolated_between_references() {
+		let calibration = Calibration::new(vec![(60.0, 20.0), (20.0, 5.0)], 40.0);
+
+		assert_eq!(calibration.blocks_per_meter(20.0), Some(5.0));
+		assert_eq!(calibration.blocks_per_meter(40.0), Some(12.5));
+		assert_eq!(calibration.blocks_per_meter(60.0), Some(20.0));
+		assert_eq!(calibration.scale(40.0), 1.0);
+		assert_eq!(calibration.scale(60.0), 1.6);
+	}
+
+	#[test]
+	fn scale_is_extrapolated_beyond_references() {
+		let calibration = Calibration::new(vec![(20.0, 5.0), (60.0, 20.0)], 40.0);
+
+		assert_eq!(calibration.blocks_per_meter(80.0), Some(27.5));
+		assert_eq!(calibration.blocks_per_meter(10.0), Some(1.25));
+		// Never down to nothing towards the horizon.
+		assert_eq!(calibration.blocks_per_meter(0.0), Some(MIN_BLOCKS_PER_METER));
+	}
+
+	#[test]
+	fn one_reference_is_the_same_everywhere() {
+		let calibration = Calibration::new(vec![(20.0, 5.0)], 40.0);
+
+		assert_eq!(calibration.blocks_per_meter(60.0), Some(5.0));
+		assert_eq!(calibration.scale(60.0), 1.0);
+	}
+
+	#[test]
+	fn without_references_there_is_no_scale() {
+		let calibration = Calibration::default();
+
+		assert_eq!(calibration.blocks_per_meter(20.0), None);
+		assert_eq!(calibration.scale(20.0), 1.0);
+		assert_eq!(calibration.to_meters(4.0, 8.0, 20.0), None);
+	}
+
+	#[test]
+	fn sizes_in_meters() {
+		let calibration = Calibration::new(vec![(20.0, 5.0), (60.0, 20.0)], 40.0);
+
+		assert_eq!(calibration.to_meters(4.0, 8.0, 60.0), Some([0.2, 0.4]));
+	}
+}
@@ -0,0 +1,115 @@
+use std::io::Read;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::spawn;
+use byteorder::{ByteOrder, LittleEndian};
+
+// Reads frames off an input on its own thread and keeps only the newest
+// complete one.
+//
+// If processing falls behind, frames that were never picked up are replaced
+// by newer ones (and counted as dropped) instead of piling up in the TCP
+// buffer, which would make latency grow without bound. Unless we want them
+// all ('keep_all', e.g. reading a recording), then reading waits.
+
+pub struct Frame {
+	pub sequence: u64,				// counted from 0, including dropped frames
+	pub timestamp: Option<u128>,	// if the input sends them
+	pub data: Vec<u8>,
+}
+
+struct Slot {
+	frame: Option<Frame>,
+	dropped: u64,
+	closed: bool,
+}
+
+pub struct LatestFrame {
+	slot: Mutex<Slot>,
+	ready: Condvar,
+	taken: Condvar,
+	keep_all: bool,
+}
+
+impl LatestFrame {
+	// Blocks until there is a frame. Returns it along with the number of frames
+	// dropped since the last one that was taken, or None once the input is
+	// closed.
+	pub fn take(&self) -> Option<(Frame, u64)>
+	{
+		let mut slot = self.slot.lock().unwrap();
+
+		loop {
+			if let Some(frame) = slot.frame.take() {
+				let dropped = slot.dropped;
+				slot.dropped = 0;
+				self.taken.notify_one();
+				return Some((frame, dropped));
+			}
+
+			if slot.closed {
+				return None;
+			}
+
+			slot = self.ready.wait(slot).unwrap();
+		}
+	}
+
+	fn put(&self, frame: Frame)
+	{
+		let mut slot = self.slot.lock().unwrap();
+
+		while self.keep_all && slot.frame.is_some() {
+			slot = self.taken.wait(slot).unwrap();
+		}
+
+		if slot.frame.replace(frame).is_some() {
+			slot.dropped += 1;
+		}
+
+		self.ready.notify_one();
+	}
+
+	fn close(&self)
+	{
+		self.slot.lock().unwrap().closed = true;
+		self.ready.notify_one();
+	}
+}
+
+// 'timestamps': every frame is preceded by a 64-bit little-endian timestamp.
+pub fn spawn_reader<R: Read + Send + 'static>(mut stream: R, frame_size: usize, timestamps: bool, keep_all: bool) -> Arc<LatestFrame>
+{
+	let latest = Arc::new(LatestFrame {
+		slot: Mutex::new(Slot { frame: None, dropped: 0, closed: false }),
+		ready: Condvar::new(),
+		taken: Condvar::new(),
+		keep_all,
+	});
+
+	let writer = latest.clone();
+
+	spawn(move || {
+		let mut timestamp = [0; 8];
+
+		for sequence in 0.. {
+			if timestamps && stream.read_exact(&mut timestamp).is_err() {
+				break;
+			}
+
+			let mut data = vec![0; frame_size];
+			if stream.read_exact(&mut data).is_err() {
+				break;
+			}
+
+			writer.put(Frame {
+				sequence,
+				timestamp: timestamps.then(|| LittleEndian::read_u64(&timestamp) as u128),
+				data,
+			});
+		}
+
+		writer.close();
+	});
+
+	latest
+}
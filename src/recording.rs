@@ -0,0 +1,220 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use byteorder::{ByteOrder, LittleEndian};
+use serde::{Deserialize, Serialize};
+use crate::camera;
+
+// Recordings of raw frames around events.
+//
+// The last --prerollms of raw frames are kept in memory. When an alarm goes
+// off they are written to a new file in --recorddir, and so is every frame
+// after, until --postrollms after the last alarm.
+//
+// A recording starts with a line "MVR <header as JSON>", followed by the
+// frames as they come in with --clock STREAM: a timestamp (ms, 64-bit little
+// endian) and the vectors. --input plays one back.
+
+#[derive(Serialize, Deserialize)]
+pub struct Header {
+	pub version: u32,
+	pub camera: String,
+	pub width: usize,
+	pub height: usize,
+}
+
+const MAGIC: &str = "MVR ";
+const VERSION: u32 = 1;
+
+pub struct Recorder {
+	frames: VecDeque<(u128, Vec<u8>)>,
+	file: Option<(String, BufWriter<File>)>,
+	until: u128,
+	written: usize,
+}
+
+pub enum Event {
+	Started(String),
+	Finished(String, usize),
+}
+
+impl Recorder {
+	pub fn new() -> Recorder {
+		Recorder {
+			frames: VecDeque::new(),
+			file: None,
+			until: 0,
+			written: 0,
+		}
+	}
+
+	// Every frame, before anything else about it is known.
+	pub fn add(&mut self, now: u128, data: &[u8], preroll: u128) -> std::io::Result<Option<Event>>
+	{
+		let mut event = None;
+
+		if now > self.until {
+			event = self.finish()?;
+		}
+
+		if self.file.is_some() {
+			self.write(now, data)?;
+			return Ok(None);
+		}
+
+		self.frames.push_back((now, data.to_vec()));
+		while self.frames.front().is_some_and(|(t, _)| now.saturating_sub(*t) > preroll) {
+			self.frames.pop_front();
+		}
+
+		Ok(event)
+	}
+
+	// An alarm went off: start recording, or record for longer.
+	pub fn trigger(&mut self, dir: &str, header: &Header, now: u128, postroll: u128) -> std::io::Result<Option<Event>>
+	{
+		self.until = now + postroll;
+
+		if self.file.is_some() {
+			return Ok(None);
+		}
+
+		let path = Path::new(dir).join(format!("{}-{}.mvr", camera::file_name(&header.camera), now));
+		let path = path.to_string_lossy().to_string();
+		let mut out = BufWriter::new(File::create(&path)?);
+
+		writeln!(out, "{}{}", MAGIC, serde_json::to_string(header)?)?;
+		self.file = Some((path.clone(), out));
+		self.written = 0;
+
+		for (t, data) in std::mem::take(&mut self.frames) {
+			self.write(t, &data)?;
+		}
+
+		Ok(Some(Event::Started(path)))
+	}
+
+	// Closes the recording, if there is one.
+	pub fn finish(&mut self) -> std::io::Result<Option<Event>>
+	{
+		match self.file.take() {
+			Some((path, mut out)) => {
+				out.flush()?;
+				Ok(Some(Event::Finished(path, self.written)))
+			},
+			None => Ok(None),
+		}
+	}
+
+	fn write(&mut self, now: u128, data: &[u8]) -> std::io::Result<()>
+	{
+		if let Some((_, out)) = self.file.as_mut() {
+			let mut timestamp = [0; 8];
+			LittleEndian::write_u64(&mut timestamp, now as u64);
+			out.write_all(&timestamp)?;
+			out.write_all(data)?;
+			self.written += 1;
+		}
+
+		Ok(())
+	}
+}
+
+impl Header {
+	pub fn new(camera: &str, width: usize, height: usize) -> Header {
+		Header {
+			version: VERSION,
+			camera: camera.to_string(),
+			width,
+			height,
+		}
+	}
+}
+
+// Opens a recording; what is left to read are the frames.
+pub fn open(path: &str) -> Result<(Header, BufReader<File>), String>
+{
+	let mut reader = BufReader::new(File::open(path).map_err(|e| format!("{}: {}", path, e))?);
+	let mut line = String::new();
+
+	reader.read_line(&mut line).map_err(|e| format!("{}: {}", path, e))?;
+
+	let header: Header = line.strip_prefix(MAGIC)
+		.ok_or(format!("{}: not a recording", path))
+		.and_then(|json| serde_json::from_str(json).map_err(|e| format!("{}: {}", path, e)))?;
+
+	if header.version != VERSION {
+		return Err(format!("{}: recording version {} is not supported", path, header.version));
+	}
+
+	Ok((header, reader))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::Read;
+
+	const FRAME: usize = 4;
+
+	fn frame(n: u8) -> Vec<u8> {
+		vec![n; FRAME]
+	}
+
+	#[test]
+	fn preroll_is_bounded() {
+		let mut recorder = Recorder::new();
+
+		for t in 0..100 {
+			recorder.add(t * 100, &frame(t as u8), 1000).unwrap();
+		}
+
+		assert_eq!(recorder.frames.len(), 11);
+		assert_eq!(recorder.frames.front().map(|(t, _)| *t), Some(8900));
+	}
+
+	#[test]
+	fn recording_is_read_back() {
+		let dir = std::env::temp_dir().join(format!("mvr-recording-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let header = Header::new("garden", 2, 1);
+		let mut recorder = Recorder::new();
+
+		recorder.add(0, &frame(0), 100).unwrap();
+		recorder.add(100, &frame(1), 100).unwrap();
+		let path = match recorder.trigger(dir.to_str().unwrap(), &header, 100, 100).unwrap() {
+			Some(Event::Started(path)) => path,
+			_ => panic!("recording did not start"),
+		};
+		recorder.add(200, &frame(2), 100).unwrap();
+		match recorder.add(300, &frame(3), 100).unwrap() {
+			Some(Event::Finished(p, frames)) => assert_eq!((p.as_str(), frames), (path.as_str(), 3)),
+			_ => panic!("recording did not finish"),
+		}
+
+		let (read, mut file) = open(&path).unwrap();
+		assert_eq!((read.camera.as_str(), read.width, read.height), ("garden", 2, 1));
+
+		for (t, n) in [(0, 0), (100, 1), (200, 2)] {
+			let mut timestamp = [0; 8];
+			let mut data = [0; FRAME];
+			file.read_exact(&mut timestamp).unwrap();
+			file.read_exact(&mut data).unwrap();
+			assert_eq!((LittleEndian::read_u64(&timestamp), data.to_vec()), (t, frame(n)));
+		}
+		assert_eq!(file.read(&mut [0; 1]).unwrap(), 0);
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn other_files_are_refused() {
+		let path = std::env::temp_dir().join(format!("mvr-not-a-recording-{}", std::process::id()));
+		std::fs::write(&path, "{}\n").unwrap();
+
+		assert!(open(path.to_str().unwrap()).is_err());
+
+		std::fs::remove_file(&path).unwrap();
+	}
+}
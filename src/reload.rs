@@ -0,0 +1,119 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, SystemTime};
+use crate::config;
+use crate::AppArgs;
+
+// Hot reload of the configuration file.
+//
+// The file is checked for changes every second, and re-read at the next check
+// after a SIGHUP even if it did not change. Every reload that passes
+// validation bumps the generation; processors compare it between frames and
+// pick up the new settings of their camera. A file with errors is reported
+// and otherwise ignored.
+//
+// What is set up once per process (listening addresses, ports, metrics) or
+// per connection (grid size, clock) is not changed by a reload.
+
+pub struct Reloader {
+	generation: AtomicU64,
+	args: RwLock<AppArgs>,
+}
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+impl Reloader {
+	pub fn new(args: AppArgs) -> Arc<Reloader> {
+		Arc::new(Reloader {
+			generation: AtomicU64::new(0),
+			args: RwLock::new(args),
+		})
+	}
+
+	pub fn generation(&self) -> u64 {
+		self.generation.load(Ordering::Acquire)
+	}
+
+	// Settings for camera 'id' as of the latest reload, with 'profile' applied
+	// if given.
+	pub fn for_camera(&self, id: &str, profile: Option<&str>) -> Result<AppArgs, String>
+	{
+		let args = self.args.read().unwrap();
+		args.configfile.for_camera(&args, id, profile)
+	}
+
+	// Watches 'path'. 'base' are the settings from the command line, which
+	// the file is applied on top of.
+	pub fn watch(self: &Arc<Self>, path: String, base: AppArgs)
+	{
+		let reloader = self.clone();
+		let mut last_modified = modified(&path);
+
+		hangup::install();
+
+		spawn(move || {
+			loop {
+				sleep(POLL_INTERVAL);
+
+				let m = modified(&path);
+				if m == last_modified && !hangup::received() {
+					continue;
+				}
+				last_modified = m;
+
+				let mut args = base.clone();
+				match config::load(&path, &mut args) {
+					Ok(file) => {
+						args.configfile = file;
+						*reloader.args.write().unwrap() = args;
+						reloader.generation.fetch_add(1, Ordering::AcqRel);
+					},
+					Err(e) => eprintln!("Not reloading configuration: {}.", e),
+				}
+			}
+		});
+	}
+}
+
+fn modified(path: &str) -> Option<SystemTime>
+{
+	std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// SIGHUP, without pulling in a crate for it; std links against libc anyway.
+#[cfg(unix)]
+mod hangup {
+	use std::sync::atomic::{AtomicBool, Ordering};
+
+	const SIGHUP: i32 = 1;
+
+	static RECEIVED: AtomicBool = AtomicBool::new(false);
+
+	extern "C" {
+		fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+	}
+
+	extern "C" fn on_hangup(_: i32) {
+		RECEIVED.store(true, Ordering::SeqCst);
+	}
+
+	pub fn install() {
+		unsafe {
+			signal(SIGHUP, on_hangup);
+		}
+	}
+
+	pub fn received() -> bool {
+		RECEIVED.swap(false, Ordering::SeqCst)
+	}
+}
+
+#[cfg(not(unix))]
+mod hangup {
+	pub fn install() {}
+
+	pub fn received() -> bool {
+		false
+	}
+}
@@ -0,0 +1,210 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use crate::camera;
+use crate::mvrprocessor::{Cluster, MotionVector};
+
+// Debug renderer (--output DEBUG), to see what the processor sees.
+//
+// Every block is coloured by the direction (hue) and magnitude (brightness) of
+// its vector; blocks that are not candidates are dimmed. Tracks updated in
+// this frame get a white box and their ID.
+//
+// Frames are written as a PPM sequence to --debugdir, or else drawn on the
+// terminal (stderr, with 24-bit colour; two rows of blocks per line) so that
+// the JSON on stdout is left alone.
+
+pub struct Renderer {
+	width: usize,
+	height: usize,
+	dir: Option<String>,
+}
+
+const SCALE: usize = 8;				// pixels per block in PPM
+const FULL_MAGNITUDE: f32 = 16.0;	// brightest at this magnitude and up
+const DIMMED: f32 = 0.3;
+const WHITE: [u8; 3] = [255, 255, 255];
+
+// 3x5 digits, a row per byte, bits from the left.
+const DIGITS: [[u8; 5]; 10] = [
+	[7, 5, 5, 5, 7], [2, 6, 2, 2, 7], [7, 1, 7, 4, 7], [7, 1, 3, 1, 7], [5, 5, 7, 1, 1],
+	[7, 4, 7, 1, 7], [7, 4, 7, 5, 7], [7, 1, 1, 1, 1], [7, 5, 7, 5, 7], [7, 5, 7, 1, 7],
+];
+
+impl Renderer {
+	pub fn new(width: usize, height: usize, dir: Option<String>) -> Renderer {
+		Renderer {
+			width,
+			height,
+			dir,
+		}
+	}
+
+	pub fn render(&self, camera: &str, frame: u64, vectors: &[MotionVector], mask: &[bool], tracks: &[&Cluster]) -> std::io::Result<()>
+	{
+		let colours: Vec<[u8; 3]> = vectors.iter().zip(mask.iter()).map(|(v, m)| colour(v, *m)).collect();
+
+		match &self.dir {
+			Some(dir) => self.ppm(dir, camera, frame, &colours, tracks),
+			None => self.ansi(camera, frame, &colours, tracks),
+		}
+	}
+
+	fn ppm(&self, dir: &str, camera: &str, frame: u64, colours: &[[u8; 3]], tracks: &[&Cluster]) -> std::io::Result<()>
+	{
+		let (w, h) = (self.width * SCALE, self.height * SCALE);
+		let mut image = vec![0u8; w * h * 3];
+
+		let mut plot = |x: usize, y: usize, c: [u8; 3]| {
+			if x < w && y < h {
+				image[(y * w + x) * 3..(y * w + x) * 3 + 3].copy_from_slice(&c);
+			}
+		};
+
+		for (i, c) in colours.iter().enumerate() {
+			let (bx, by) = ((i % self.width) * SCALE, (i / self.width) * SCALE);
+			for y in by..by + SCALE {
+				for x in bx..bx + SCALE {
+					plot(x, y, *c);
+				}
+			}
+		}
+
+		for t in tracks {
+			// bbox is [top, right, bottom, left], inclusive, in blocks
+			let (top, right) = (t.bbox[0] as usize * SCALE, (t.bbox[1] as usize + 1) * SCALE - 1);
+			let (bottom, left) = ((t.bbox[2] as usize + 1) * SCALE - 1, t.bbox[3] as usize * SCALE);
+
+			for x in left..=right {
+				plot(x, top, WHITE);
+				plot(x, bottom, WHITE);
+			}
+			for y in top..=bottom {
+				plot(left, y, WHITE);
+				plot(right, y, WHITE);
+			}
+
+			// ID above the box, or inside it at the top edge of the image.
+			let y = if top >= 7 { top - 7 } else { top + 2 };
+			for (n, digit) in t.id.to_string().bytes().enumerate() {
+				let glyph = DIGITS[(digit - b'0') as usize];
+				for (row, bits) in glyph.iter().enumerate() {
+					for col in 0..3 {
+						if bits & (4 >> col) != 0 {
+							plot(left + n * 4 + col, y + row, WHITE);
+						}
+					}
+				}
+			}
+		}
+
+		let path = Path::new(dir).join(format!("{}-{:06}.ppm", camera::file_name(camera), frame));
+		let mut out = BufWriter::new(File::create(path)?);
+
+		write!(out, "P6\n{} {}\n255\n", w, h)?;
+		out.write_all(&image)?;
+		out.flush()
+	}
+
+	fn ansi(&self, camera: &str, frame: u64, colours: &[[u8; 3]], tracks: &[&Cluster]) -> std::io::Result<()>
+	{
+		let on_box = |x: usize, y: usize| tracks.iter().any(|t| {
+			let (top, right, bottom, left) = (t.bbox[0] as usize, t.bbox[1] as usize, t.bbox[2] as usize, t.bbox[3] as usize);
+			(x >= left && x <= right && y >= top && y <= bottom) &&
+				(x == left || x == right || y == top || y == bottom)
+		});
+		let at = |x: usize, y: usize| {
+			if y >= self.height {
+				[0, 0, 0]
+			} else if on_box(x, y) {
+				WHITE
+			} else {
+				colours[y * self.width + x]
+			}
+		};
+
+		// Cursor home; the view is drawn over the previous one.
+		let mut view = String::from("\x1b[H");
+		view.push_str(&format!("camera {} frame {}\x1b[K\n", camera, frame));
+
+		for y in (0..self.height).step_by(2) {
+			for x in 0..self.width {
+				let (upper, lower) = (at(x, y), at(x, y + 1));
+				view.push_str(&format!(
+					"\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m\u{2580}",
+					upper[0], upper[1], upper[2], lower[0], lower[1], lower[2]
+				));
+			}
+			view.push_str("\x1b[0m\n");
+		}
+
+		for t in tracks {
+			view.push_str(&format!(
+				"#{} [{},{},{},{}] dir {:.0} mag {:.1}\x1b[K\n",
+				t.id, t.bbox[0], t.bbox[1], t.bbox[2], t.bbox[3], t.dir, t.mag
+			));
+		}
+		view.push_str("\x1b[J");
+
+		let mut err = std::io::stderr().lock();
+		err.write_all(view.as_bytes())?;
+		err.flush()
+	}
+}
+
+fn colour(v: &MotionVector, candidate: bool) -> [u8; 3]
+{
+	let value = (v.mag / FULL_MAGNITUDE).min(1.0) * if candidate { 1.0 } else { DIMMED };
+
+	hsv(v.dir % 360.0, 1.0, value)
+}
+
+fn hsv(hue: f32, saturation: f32, value: f32) -> [u8; 3]
+{
+	let c = value * saturation;
+	let x = c * (1.0 - ((hue / 60.0) % 2.0 - 1.0).abs());
+	let m = value - c;
+
+	let (r, g, b) = match (hue / 60.0) as u32 {
+		0 => (c, x, 0.0),
+		1 => (x, c, 0.0),
+		2 => (0.0, c, x),
+		3 => (0.0, x, c),
+		4 => (x, 0.0, c),
+		_ => (c, 0.0, x),
+	};
+
+	[((r + m) * 255.0) as u8, ((g + m) * 255.0) as u8, ((b + m) * 255.0) as u8]
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn primary_hues() {
+		assert_eq!(hsv(0.0, 1.0, 1.0), [255, 0, 0]);
+		assert_eq!(hsv(60.0, 1.0, 1.0), [255, 255, 0]);
+		assert_eq!(hsv(120.0, 1.0, 1.0), [0, 255, 0]);
+		assert_eq!(hsv(180.0, 1.0, 1.0), [0, 255, 255]);
+		assert_eq!(hsv(240.0, 1.0, 1.0), [0, 0, 255]);
+		assert_eq!(hsv(300.0, 1.0, 1.0), [255, 0, 255]);
+	}
+
+	#[test]
+	fn value_and_saturation() {
+		assert_eq!(hsv(0.0, 1.0, 0.0), [0, 0, 0]);
+		assert_eq!(hsv(0.0, 1.0, 0.5), [127, 0, 0]);
+		assert_eq!(hsv(200.0, 0.0, 1.0), [255, 255, 255]);
+	}
+
+	#[test]
+	fn non_candidates_are_dimmed() {
+		let mut v = MotionVector::new();
+		v.dir = 480.0;
+		v.mag = FULL_MAGNITUDE * 2.0;
+
+		assert_eq!(colour(&v, true), [0, 255, 0]);
+		assert_eq!(colour(&v, false), [0, (255.0 * DIMMED) as u8, 0]);
+	}
+}
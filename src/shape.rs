@@ -0,0 +1,158 @@
+use serde::Serialize;
+use crate::mvrprocessor::Cluster;
+use crate::perspective::Calibration;
+
+// Size and shape limits for clusters.
+//
+// Insects near the lens give tiny clusters, light changes give huge ones.
+// Limits are given in macroblocks or, if 'pixels' is set, in sensor pixels
+// (a macroblock is 16x16 pixels). Area is the number of active blocks in the
+// cluster, width and height are of its bounding box, aspect is width / height.
+// Any limit that is not set is not checked.
+//
+// With a perspective calibration, limits are scaled to the row the cluster
+// stands on (see perspective.rs).
+
+const BLOCK_SIZE: f32 = 16.0;
+
+// Serialized with the names of the settings.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ShapeFilter {
+	#[serde(rename = "minarea")]
+	pub min_area: Option<f32>,
+	#[serde(rename = "maxarea")]
+	pub max_area: Option<f32>,
+	#[serde(rename = "minwidth")]
+	pub min_width: Option<f32>,
+	#[serde(rename = "maxwidth")]
+	pub max_width: Option<f32>,
+	#[serde(rename = "minheight")]
+	pub min_height: Option<f32>,
+	#[serde(rename = "maxheight")]
+	pub max_height: Option<f32>,
+	#[serde(rename = "minaspect")]
+	pub min_aspect: Option<f32>,
+	#[serde(rename = "maxaspect")]
+	pub max_aspect: Option<f32>,
+}
+
+impl ShapeFilter {
+	// Converts limits given in pixels to macroblocks.
+	pub fn in_blocks(&self, pixels: bool) -> ShapeFilter
+	{
+		if !pixels {
+			return self.clone();
+		}
+
+		let length = |v: Option<f32>| v.map(|v| v / BLOCK_SIZE);
+		let area = |v: Option<f32>| v.map(|v| v / (BLOCK_SIZE * BLOCK_SIZE));
+
+		ShapeFilter {
+			min_area: area(self.min_area),
+			max_area: area(self.max_area),
+			min_width: length(self.min_width),
+			max_width: length(self.max_width),
+			min_height: length(self.min_height),
+			max_height: length(self.max_height),
+			min_aspect: self.min_aspect,
+			max_aspect: self.max_aspect,
+		}
+	}
+
+	// 'reduction_factor' is how much the candidates were thinned out before
+	// clustering; each point then stands for that many blocks.
+	pub fn accepts(&self, c: &Cluster, reduction_factor: usize, calibration: &Calibration) -> bool
+	{
+		let area = (c.points.len() * reduction_factor) as f32;
+		let width = (c.bbox[1] - c.bbox[3] + 1) as f32;
+		let height = (c.bbox[2] - c.bbox[0] + 1) as f32;
+		let aspect = width / height;
+
+		let scale = calibration.scale(c.bbox[2] as f32);
+
+		within(area, self.min_area, self.max_area, scale * scale)
+			&& within(width, self.min_width, self.max_width, scale)
+			&& within(height, self.min_height, self.max_height, scale)
+			&& within(aspect, self.min_aspect, self.max_aspect, 1.0)
+	}
+}
+
+fn within(v: f32, min: Option<f32>, max: Option<f32>, scale: f32) -> bool
+{
+	min.is_none_or(|min| v >= min * scale) && max.is_none_or(|max| v <= max * scale)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mvrprocessor::MotionVector;
+
+	// A cluster of 'points' points in a 'width' by 'height' box standing on
+	// row 'bottom'.
+	fn cluster(points: usize, width: i16, height: i16, bottom: i16) -> Cluster {
+		let mut c = Cluster::new();
+		c.points = vec![MotionVector::new(); points];
+		c.bbox = [bottom - height + 1, 10 + width - 1, bottom, 10];
+		c
+	}
+
+	#[test]
+	fn unset_limits_accept_anything() {
+		let filter = ShapeFilter::default();
+		assert!(filter.accepts(&cluster(1, 1, 1, 10), 1, &Calibration::default()));
+		assert!(filter.accepts(&cluster(5000, 100, 50, 60), 1, &Calibration::default()));
+	}
+
+	#[test]
+	fn area_counts_reduced_points() {
+		let filter = ShapeFilter { min_area: Some(8.0), ..Default::default() };
+		let c = cluster(4, 4, 2, 10);
+
+		assert!(!filter.accepts(&c, 1, &Calibration::default()));
+		assert!(filter.accepts(&c, 2, &Calibration::default()));
+	}
+
+	#[test]
+	fn size_and_aspect_limits() {
+		let filter = ShapeFilter {
+			max_width: Some(5.0),
+			min_height: Some(2.0),
+			max_aspect: Some(1.0),
+			..Default::default()
+		};
+		let none = Calibration::default();
+
+		assert!(filter.accepts(&cluster(6, 2, 3, 10), 1, &none));
+		assert!(!filter.accepts(&cluster(6, 6, 6, 10), 1, &none));	// too wide
+		assert!(!filter.accepts(&cluster(6, 1, 1, 10), 1, &none));	// too low
+		assert!(!filter.accepts(&cluster(6, 4, 2, 10), 1, &none));	// lying down
+	}
+
+	#[test]
+	fn limits_scale_with_perspective() {
+		let filter = ShapeFilter { min_width: Some(4.0), ..Default::default() };
+		// Things at row 60 are twice as big as at the reference row 30.
+		let calibration = Calibration::new(vec![(0.0, 5.0), (60.0, 20.0)], 30.0);
+
+		assert!(filter.accepts(&cluster(10, 4, 4, 30), 1, &calibration));
+		assert!(!filter.accepts(&cluster(10, 4, 4, 60), 1, &calibration));
+		assert!(filter.accepts(&cluster(10, 8, 4, 60), 1, &calibration));
+	}
+
+	#[test]
+	fn pixels_are_converted_to_blocks() {
+		let filter = ShapeFilter {
+			min_area: Some(512.0),
+			max_width: Some(64.0),
+			min_aspect: Some(0.5),
+			..Default::default()
+		};
+
+		let blocks = filter.in_blocks(true);
+		assert_eq!(blocks.min_area, Some(2.0));
+		assert_eq!(blocks.max_width, Some(4.0));
+		assert_eq!(blocks.min_aspect, Some(0.5));
+
+		assert_eq!(filter.in_blocks(false).max_width, Some(64.0));
+	}
+}
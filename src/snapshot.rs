@@ -0,0 +1,76 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::camera;
+use crate::mvrprocessor::{Cluster, MotionVector};
+
+// Snapshot of what the processor saw in a frame: the whole vector grid, which
+// blocks were candidates and which cluster they ended up in.
+//
+// All grids are flat, row by row, 'width' blocks wide. In 'labels', 0 is no
+// cluster and n the n-th cluster of the frame's message.
+//
+// Taken on request (control socket) or when an alarm goes off (with
+// --snapshotonalarm). Emitted as a message, or written to a file of its own
+// in --snapshotdir.
+
+#[derive(Serialize, Deserialize)]
+pub struct SnapshotMessage {
+	pub snapshot: SnapshotData,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SnapshotData {
+	pub reason: String,		// "request" or "alarm"
+	pub frame: u64,
+	pub time: u64,			// ms since epoch
+	pub width: usize,
+	pub height: usize,
+	pub dx: Vec<i8>,
+	pub dy: Vec<i8>,
+	pub sad: Vec<u16>,
+	pub mask: Vec<u8>,		// 1 for candidates
+	pub labels: Vec<u16>,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn take(reason: &str, frame: u64, time: u128, width: usize, height: usize,
+	vectors: &[MotionVector], mask: &[bool], clusters: &[Cluster]) -> SnapshotMessage
+{
+	let mut labels = vec![0; vectors.len()];
+
+	for (n, c) in clusters.iter().enumerate() {
+		for p in c.points.iter() {
+			labels[p.y as usize * width + p.x as usize] = n as u16 + 1;
+		}
+	}
+
+	SnapshotMessage {
+		snapshot: SnapshotData {
+			reason: reason.to_string(),
+			frame,
+			time: time as u64,
+			width,
+			height,
+			dx: vectors.iter().map(|v| v.dx).collect(),
+			dy: vectors.iter().map(|v| v.dy).collect(),
+			sad: vectors.iter().map(|v| v.sad).collect(),
+			mask: mask.iter().map(|m| *m as u8).collect(),
+			labels,
+		}
+	}
+}
+
+// Writes 'snapshot' to 'dir' as <camera>-<time>-<frame>.json and returns the
+// path.
+pub fn write(dir: &str, camera: &str, snapshot: &SnapshotMessage) -> std::io::Result<String>
+{
+	let path = Path::new(dir).join(format!("{}-{}-{}.json", camera::file_name(camera), snapshot.snapshot.time, snapshot.snapshot.frame));
+	let mut out = BufWriter::new(File::create(&path)?);
+
+	serde_json::to_writer(&mut out, snapshot)?;
+	out.flush()?;
+
+	Ok(path.to_string_lossy().to_string())
+}
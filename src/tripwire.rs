@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use crate::mvrprocessor::Cluster;
+
+// Virtual tripwires: count tracks crossing line segments.
+//
+// A line goes 'from' -> 'to' on the vector grid. Crossing it from its left
+// side to its right side (as seen when standing on 'from', looking at 'to')
+// counts as 'in', the other way is 'out'.
+//
+// The trajectory of a track is the centre of its bounding box over time. To
+// not count a track that jitters on the line over and over, a track only has a
+// side once it is at least 'margin' blocks away from the line, and a crossing
+// is only counted when that side changes.
+//
+// Lines are read from a JSON file, e.g.:
+// [
+//   { "name": "doorway", "from": [60,10], "to": [60,50], "margin": 1.5 }
+// ]
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Line {
+	pub name: String,
+	pub from: (f32, f32),
+	pub to: (f32, f32),
+	#[serde(default = "default_margin")]
+	pub margin: f32,
+}
+
+fn default_margin() -> f32 {
+	1.0
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Crossing {
+	pub line: String,
+	pub track: usize,
+	pub direction: String,		// "in" or "out"
+	pub time: u128,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LineCount {
+	pub name: String,
+	#[serde(rename = "in")]
+	pub count_in: u64,
+	#[serde(rename = "out")]
+	pub count_out: u64,
+}
+
+// Last position of a track where it was clearly on one side of a line.
+#[derive(Clone, Copy)]
+struct Side {
+	left: bool,
+	at: (f32, f32),
+}
+
+pub struct Tripwires {
+	lines: Vec<Line>,
+	counts: Vec<LineCount>,
+	sides: Vec<HashMap<usize, Side>>,
+}
+
+impl Tripwires {
+	pub fn new(lines: Vec<Line>) -> Tripwires {
+		Tripwires {
+			counts: lines.iter().map(|l| LineCount { name: l.name.clone(), count_in: 0, count_out: 0 }).collect(),
+			sides: lines.iter().map(|_| HashMap::new()).collect(),
+			lines,
+		}
+	}
+
+	pub fn counts(&self) -> &[LineCount] {
+		&self.counts
+	}
+
+	pub fn evaluate(&mut self, history: &[Cluster], now: u128) -> Vec<Crossing>
+	{
+		let mut crossings = vec![];
+
+		for l in 0..self.lines.len() {
+			let line = &self.lines[l];
+			let sides = &mut self.sides[l];
+
+			sides.retain(|track_id, _| history.iter().any(|c| c.id == *track_id));
+
+			for track in history {
+				if track.active != now {
+					continue;
+				}
+
+				let at = track.centre();
+				let distance = signed_distance(line, at);
+
+				if distance.abs() < line.margin {
+					// Too close to call.
+					continue;
+				}
+
+				let side = Side { left: distance < 0.0, at };
+
+				match sides.insert(track.id, side) {
+					Some(last) if last.left != side.left && intersects(line.from, line.to, last.at, at) => {
+						let direction = if last.left {
+							self.counts[l].count_in += 1;
+							"in"
+						} else {
+							self.counts[l].count_out += 1;
+							"out"
+						};
+
+						crossings.push(Crossing {
+							line: line.name.clone(),
+							track: track.id,
+							direction: direction.to_string(),
+							time: now,
+						});
+					},
+					_ => {}
+				}
+			}
+		}
+
+		crossings
+	}
+}
+
+pub fn load_lines(path: &str) -> Result<Vec<Line>, String>
+{
+	let json = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+	serde_json::from_str(&json).map_err(|e| format!("{}: {}", path, e))
+}
+
+// Distance of point from (infinite) line; negative on the left. Note that y
+// grows downwards on the grid.
+fn signed_distance(line: &Line, p: (f32, f32)) -> f32
+{
+	let (dx, dy) = (line.to.0 - line.from.0, line.to.1 - line.from.1);
+	let length = (dx * dx + dy * dy).sqrt();
+
+	if length == 0.0 {
+		return 0.0;
+	}
+
+	(dx * (p.1 - line.from.1) - dy * (p.0 - line.from.0)) / length
+}
+
+// Do segments a1-a2 and b1-b2 intersect?
+fn intersects(a1: (f32, f32), a2: (f32, f32), b1: (f32, f32), b2: (f32, f32)) -> bool
+{
+	let d1 = cross(b1, b2, a1);
+	let d2 = cross(b1, b2, a2);
+	let d3 = cross(a1, a2, b1);
+	let d4 = cross(a1, a2, b2);
+
+	((d1 > 0.0) != (d2 > 0.0)) && ((d3 > 0.0) != (d4 > 0.0))
+}
+
+fn cross(o: (f32, f32), a: (f32, f32), b: (f32, f32)) -> f32
+{
+	(a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn doorway() -> Line {
+		Line { name: "doorway".to_string(), from: (60.0, 10.0), to: (60.0, 50.0), margin: 1.5 }
+	}
+
+	// A track with its centre at (x, y).
+	fn track(id: usize, x: i16, y: i16, now: u128) -> Cluster {
+		Cluster { id, bbox: [y - 2, x + 2, y + 2, x - 2], active: now, ..Cluster::new() }
+	}
+
+	#[test]
+	fn distance_is_signed_by_side() {
+		let line = doorway();
+
+		assert_eq!(signed_distance(&line, (70.0, 30.0)), -10.0);
+		assert_eq!(signed_distance(&line, (50.0, 30.0)), 10.0);
+		assert_eq!(signed_distance(&line, (60.0, 80.0)), 0.0);
+
+		let point = Line { to: line.from, ..line };
+		assert_eq!(signed_distance(&point, (70.0, 30.0)), 0.0);
+	}
+
+	#[test]
+	fn segments_intersect() {
+		assert!(intersects((0.0, 0.0), (10.0, 10.0), (0.0, 10.0), (10.0, 0.0)));
+		assert!(!intersects((0.0, 0.0), (10.0, 0.0), (0.0, 5.0), (10.0, 5.0)));
+		// Would cross if the line went on.
+		assert!(!intersects((0.0, 0.0), (10.0, 0.0), (20.0, -5.0), (20.0, 5.0)));
+	}
+
+	#[test]
+	fn crossings_are_counted_both_ways() {
+		let mut wires = Tripwires::new(vec![doorway()]);
+
+		assert!(wires.evaluate(&[track(1, 70, 30, 0)], 0).is_empty());
+
+		let crossings = wires.evaluate(&[track(1, 50, 30, 1)], 1);
+		assert_eq!(crossings.len(), 1);
+		assert_eq!(crossings[0].direction, "in");
+
+		let crossings = wires.evaluate(&[track(1, 70, 30, 2)], 2);
+		assert_eq!(crossings.len(), 1);
+		assert_eq!(crossings[0].direction, "out");
+
+		assert_eq!((wires.counts()[0].count_in, wires.counts()[0].count_out), (1, 1));
+	}
+
+	#[test]
+	fn jitter_on_the_line_counts_once() {
+		let mut wires = Tripwires::new(vec![doorway()]);
+
+		wires.evaluate(&[track(1, 65, 30, 0)], 0);
+		for (now, x) in [59, 61, 60, 59, 55].iter().enumerate() {
+			wires.evaluate(&[track(1, *x, 30, now as u128 + 1)], now as u128 + 1);
+		}
+
+		assert_eq!((wires.counts()[0].count_in, wires.counts()[0].count_out), (1, 0));
+	}
+
+	#[test]
+	fn passing_beside_the_line_is_not_a_crossing() {
+		let mut wires = Tripwires::new(vec![doorway()]);
+
+		wires.evaluate(&[track(1, 70, 60, 0)], 0);
+		wires.evaluate(&[track(1, 50, 60, 1)], 1);
+
+		assert_eq!((wires.counts()[0].count_in, wires.counts()[0].count_out), (0, 0));
+	}
+}
@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use crate::mvrprocessor::Cluster;
+
+// Named inclusion zones with rules that raise alarms.
+//
+// Zones are polygons on the vector grid (same coordinates as MotionVector.x/y).
+// A track is in a zone when the centre of its bounding box is. Rules are
+// evaluated on tracks in 'history' that were updated this frame, and each rule
+// fires at most once per track per visit to the zone.
+//
+// Zones are read from a JSON file, e.g.:
+// [
+//   {
+//     "name": "door",
+//     "polygon": [[10,10], [40,10], [40,30], [10,30]],
+//     "rules": [
+//       { "type": "presence", "minSize": 10, "minDuration": 500 },
+//       { "type": "enter" },
+//       { "type": "leave" },
+//       { "type": "direction", "from": 45, "to": 135 }
+//     ],
+//     "allowedDirections": [[315, 45]],
+//     "minMagnitude": 2.5
+//   }
+// ]
+//
+// Tracks that move in a direction the zone does not allow are never the cause
+// of an alarm in it (see direction.rs).
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Zone {
+	pub name: String,
+	pub polygon: Vec<(f32, f32)>,
+	#[serde(default)]
+	pub rules: Vec<Rule>,
+	#[serde(default, rename = "allowedDirections")]
+	pub allowed_directions: Vec<(f32, f32)>,
+	#[serde(default, rename = "minMagnitude")]
+	pub min_magnitude: f32,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Rule {
+	Presence { minSize: usize, minDuration: u64 },	// track of at least N blocks in zone for T ms
+	Enter,											// track entered zone
+	Leave,											// track left zone (or vanished while in it)
+	Direction { from: f32, to: f32 },				// track in zone moving within range (degrees)
+}
+
+impl Rule {
+	fn name(&self) -> &'static str {
+		match self {
+			Rule::Presence { .. } => "presence",
+			Rule::Enter => "enter",
+			Rule::Leave => "leave",
+			Rule::Direction { .. } => "direction",
+		}
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Alarm {
+	pub zone: String,
+	pub rule: String,
+	pub track: usize,
+	pub time: u128,
+}
+
+struct Visit {
+	entered: u128,
+	fired: Vec<bool>,
+}
+
+pub struct ZoneEngine {
+	zones: Vec<Zone>,
+	visits: Vec<HashMap<usize, Visit>>,
+}
+
+impl ZoneEngine {
+	pub fn new(zones: Vec<Zone>) -> ZoneEngine {
+		ZoneEngine {
+			visits: zones.iter().map(|_| HashMap::new()).collect(),
+			zones,
+		}
+	}
+
+	pub fn evaluate(&mut self, history: &[Cluster], now: u128) -> Vec<Alarm>
+	{
+		let mut alarms = vec![];
+
+		for z in 0..self.zones.len() {
+			let zone = &self.zones[z];
+			let visits = &mut self.visits[z];
+
+			// Tracks that were in the zone but are gone from history.
+			visits.retain(|track_id, _| {
+				let alive = history.iter().any(|c| c.id == *track_id);
+				if !alive && zone.rules.iter().any(|r| matches!(r, Rule::Leave)) {
+					alarms.push(alarm(zone, "leave", *track_id, now));
+				}
+				alive
+			});
+
+			for track in history {
+				if track.active != now {
+					// Not seen this frame; nothing changed.
+					continue;
+				}
+
+				let (x, y) = track.centre();
+				let inside = contains(&zone.polygon, x, y);
+
+				if !inside {
+					if visits.remove(&track.id).is_some() && zone.rules.iter().any(|r| matches!(r, Rule::Leave)) {
+						alarms.push(alarm(zone, "leave", track.id, now));
+					}
+					continue;
+				}
+
+				if track.disallowed {
+					continue;
+				}
+
+				let visit = visits.entry(track.id).or_insert_with(|| Visit {
+					entered: now,
+					fired: vec![false; zone.rules.len()],
+				});
+
+				for r in 0..zone.rules.len() {
+					if visit.fired[r] {
+						continue;
+					}
+
+					let hit = match &zone.rules[r] {
+						Rule::Presence { minSize, minDuration } => {
+							track.size >= *minSize && now.saturating_sub(visit.entered) >= *minDuration as u128
+						},
+						Rule::Enter => true,
+						Rule::Leave => false,
+						Rule::Direction { from, to } => within_range(track.dir, *from, *to),
+					};
+
+					if hit {
+						visit.fired[r] = true;
+						alarms.push(alarm(zone, zone.rules[r].name(), track.id, now));
+					}
+				}
+			}
+		}
+
+		alarms
+	}
+}
+
+pub fn load_zones(path: &str) -> Result<Vec<Zone>, String>
+{
+	let json = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+	serde_json::from_str(&json).map_err(|e| format!("{}: {}", path, e))
+}
+
+// Is 'dir' within the range 'from' to 'to' (clockwise, in degrees)? A range
+// may wrap around 360, e.g. from 315 to 45.
+pub fn within_range(dir: f32, from: f32, to: f32) -> bool
+{
+	if from <= to {
+		dir >= from && dir <= to
+	} else {
+		dir >= from || dir <= to
+	}
+}
+
+fn alarm(zone: &Zone, rule: &str, track: usize, now: u128) -> Alarm
+{
+	Alarm {
+		zone: zone.name.clone(),
+		rule: rule.to_string(),
+		track,
+		time: now,
+	}
+}
+
+// Ray casting.
+pub fn contains(polygon: &[(f32, f32)], x: f32, y: f32) -> bool
+{
+	let mut inside = false;
+	let mut j = polygon.len().wrapping_sub(1);
+
+	for i in 0..polygon.len() {
+		let (xi, yi) = polygon[i];
+		let (xj, yj) = polygon[j];
+
+		if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+			inside = !inside;
+		}
+
+		j = i;
+	}
+
+	inside
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const SQUARE: [(f32, f32); 4] = [(10.0, 10.0), (40.0, 10.0), (40.0, 30.0), (10.0, 30.0)];
+
+	fn track(id: usize, bbox: [i16; 4], size: usize, now: u128) -> Cluster {
+		Cluster { id, bbox, size, active: now, ..Cluster::new() }
+	}
+
+	fn zone(rules: Vec<Rule>) -> Zone {
+		Zone {
+			name: "door".to_string(),
+			polygon: SQUARE.to_vec(),
+			rules,
+			allowed_directions: vec![],
+			min_magnitude: 0.0,
+		}
+	}
+
+	#[test]
+	fn contains_points_inside_polygon() {
+		assert!(contains(&SQUARE, 20.0, 20.0));
+		assert!(!contains(&SQUARE, 5.0, 20.0));
+		assert!(!contains(&SQUARE, 20.0, 35.0));
+
+		// Concave: an L, without its upper right.
+		let l = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (20.0, 10.0), (20.0, 20.0), (0.0, 20.0)];
+		assert!(contains(&l, 5.0, 5.0));
+		assert!(contains(&l, 15.0, 15.0));
+		assert!(!contains(&l, 15.0, 5.0));
+
+		assert!(!contains(&[], 0.0, 0.0));
+	}
+
+	#[test]
+	fn ranges_wrap_around() {
+		assert!(within_range(90.0, 45.0, 135.0));
+		assert!(!within_range(180.0, 45.0, 135.0));
+		assert!(within_range(350.0, 315.0, 45.0));
+		assert!(within_range(10.0, 315.0, 45.0));
+		assert!(!within_range(180.0, 315.0, 45.0));
+	}
+
+	#[test]
+	fn rules_fire_once_per_visit() {
+		let mut engine = ZoneEngine::new(vec![zone(vec![Rule::Enter, Rule::Leave])]);
+
+		let alarms = engine.evaluate(&[track(1, [15, 25, 25, 15], 10, 100)], 100);
+		assert_eq!(alarms.iter().map(|a| a.rule.as_str()).collect::<Vec<_>>(), ["enter"]);
+
+		assert!(engine.evaluate(&[track(1, [16, 26, 26, 16], 10, 200)], 200).is_empty());
+
+		let alarms = engine.evaluate(&[track(1, [40, 60, 50, 50], 10, 300)], 300);
+		assert_eq!(alarms.iter().map(|a| a.rule.as_str()).collect::<Vec<_>>(), ["leave"]);
+
+		let alarms = engine.evaluate(&[track(1, [15, 25, 25, 15], 10, 400)], 400);
+		assert_eq!(alarms.iter().map(|a| a.rule.as_str()).collect::<Vec<_>>(), ["enter"]);
+	}
+
+	#[test]
+	fn presence_needs_size_and_duration() {
+		let mut engine = ZoneEngine::new(vec![zone(vec![Rule::Presence { minSize: 10, minDuration: 500 }])]);
+
+		assert!(engine.evaluate(&[track(1, [15, 25, 25, 15], 20, 0)], 0).is_empty());
+		assert!(engine.evaluate(&[track(1, [15, 25, 25, 15], 5, 600)], 600).is_empty());
+		assert_eq!(engine.evaluate(&[track(1, [15, 25, 25, 15], 20, 700)], 700).len(), 1);
+		assert!(engine.evaluate(&[track(1, [15, 25, 25, 15], 20, 800)], 800).is_empty());
+	}
+
+	#[test]
+	fn disallowed_tracks_raise_nothing() {
+		let mut engine = ZoneEngine::new(vec![zone(vec![Rule::Enter])]);
+		let t = Cluster { disallowed: true, ..track(1, [15, 25, 25, 15], 10, 100) };
+
+		assert!(engine.evaluate(&[t], 100).is_empty());
+	}
+}